@@ -1,5 +1,5 @@
-use windres::Build;
-
 fn main() {
-    Build::new().compile("resources.rc").unwrap();
+    // icon and version info are Windows resources
+    #[cfg(windows)]
+    windres::Build::new().compile("resources.rc").unwrap();
 }
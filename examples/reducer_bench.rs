@@ -0,0 +1,112 @@
+//! Measures per-frame cost of the color reducers on frames which `Screen` produces from a
+//! 4K display. Run with `cargo run --release --example reducer_bench`.
+
+#[path = "../src/screen/reducer.rs"]
+mod reducer;
+
+use std::{
+    num::NonZeroU32,
+    time::{Duration, Instant},
+};
+
+use fast_image_resize as fir;
+use reducer::{border_zones, ZoneReducer};
+
+const LED_HORIZONTAL: usize = 27;
+const LED_VERTICAL: usize = 14;
+const ITERATIONS: u32 = 200;
+
+fn main() {
+    // mip levels of a 3840x2160 frame, mean uses level 7, other reducers level 4
+    for scale_factor in [7, 4, 3, 2] {
+        let width = 3840 >> scale_factor;
+        let height = 2160 >> scale_factor;
+        let pixels = test_frame(width, height);
+        let zones = border_zones(width, height, LED_HORIZONTAL, LED_VERTICAL + 2);
+        let mut zone_reducer = ZoneReducer::default();
+
+        println!("{}x{} (mip level {}):", width, height, scale_factor);
+
+        report("mean", || {
+            let mut pixels = pixels.clone();
+            let source = fir::Image::from_slice_u8(
+                NonZeroU32::new(width as u32).unwrap(),
+                NonZeroU32::new(height as u32).unwrap(),
+                &mut pixels,
+                fir::PixelType::U8x4,
+            )
+            .unwrap();
+            let mut dest = fir::Image::new(
+                NonZeroU32::new(LED_HORIZONTAL as u32).unwrap(),
+                NonZeroU32::new(LED_VERTICAL as u32 + 2).unwrap(),
+                fir::PixelType::U8x4,
+            );
+            let mut resizer =
+                fir::Resizer::new(fir::ResizeAlg::Convolution(fir::FilterType::Bilinear));
+            let start = Instant::now();
+            resizer
+                .resize(&source.view(), &mut dest.view_mut())
+                .unwrap();
+            start.elapsed()
+        });
+        report("median", || {
+            time(|| {
+                for &(_, _, zone) in &zones {
+                    zone_reducer.median(&pixels, width, zone);
+                }
+            })
+        });
+        report("mode", || {
+            time(|| {
+                for &(_, _, zone) in &zones {
+                    zone_reducer.mode(&pixels, width, zone);
+                }
+            })
+        });
+        for clusters in [2, 3] {
+            report(&format!("dominant k={}", clusters), || {
+                time(|| {
+                    for &(_, _, zone) in &zones {
+                        zone_reducer.dominant(&pixels, width, zone, clusters);
+                    }
+                })
+            });
+        }
+    }
+}
+
+/// Every other pixel red or blue with some noise, the case where mean gives purple
+fn test_frame(width: usize, height: usize) -> Vec<u8> {
+    let mut seed = 0x2545_f491_u32;
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let noise = (seed & 0x1f) as u8;
+            let pixel = if (x + y) % 2 == 0 {
+                [noise, noise, 220 + noise / 2, 255]
+            } else {
+                [220 + noise / 2, noise, noise, 255]
+            };
+            pixels.extend_from_slice(&pixel);
+        }
+    }
+    pixels
+}
+
+fn time(mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+fn report(name: &str, mut run: impl FnMut() -> Duration) {
+    let total: Duration = (0..ITERATIONS).map(|_| run()).sum();
+    println!(
+        "  {:<14} {:>8.1} µs/frame",
+        name,
+        total.as_secs_f64() * 1e6 / ITERATIONS as f64
+    );
+}
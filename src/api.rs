@@ -0,0 +1,524 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Sender},
+        Arc,
+    },
+    thread::{spawn, JoinHandle},
+    time::Duration,
+};
+
+use log::{info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+    color_sender_task::{ColorSenderTask, PipelineState},
+    config::{MASTER_BRIGHTNESS_RANGE, MULTIPLIER_RANGE},
+    metrics::Metrics,
+    preview::{self, Preview},
+    Message,
+};
+
+/// Main loop is busy only while pipelines are being stopped or restarted
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Status {
+    /// At least one pipeline is running
+    pub running: bool,
+    pub profile: Option<String>,
+    pub profiles: Vec<String>,
+    /// Brightness set through the API, `None` keeps brightness of the config
+    pub brightness: Option<f64>,
+    pub master_brightness: f64,
+    /// Environment variable fixing master brightness, changing it is rejected while it is set
+    pub master_brightness_variable: Option<String>,
+    pub pipelines: Vec<PipelineStatus>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PipelineStatus {
+    pub name: String,
+    pub running: bool,
+    pub state: PipelineState,
+    pub target: String,
+    pub fps: f32,
+    pub output_fps: f32,
+    pub target_output_fps: u32,
+    pub reachable: bool,
+    pub last_error: Option<String>,
+    pub power_limited: bool,
+}
+
+impl Status {
+    pub fn new(
+        senders: &[ColorSenderTask],
+        profile: Option<&str>,
+        profiles: Vec<String>,
+        brightness: Option<f64>,
+        master_brightness: f64,
+        master_brightness_variable: Option<&str>,
+    ) -> Self {
+        Status {
+            running: senders.iter().any(ColorSenderTask::is_running),
+            profile: profile.map(str::to_string),
+            profiles,
+            brightness,
+            master_brightness,
+            master_brightness_variable: master_brightness_variable.map(str::to_string),
+            pipelines: senders
+                .iter()
+                .map(|sender| {
+                    let status = sender.status();
+                    PipelineStatus {
+                        name: sender.name().to_string(),
+                        running: sender.is_running(),
+                        state: status.state,
+                        target: sender.target().to_string(),
+                        fps: status.fps,
+                        output_fps: status.output_fps,
+                        target_output_fps: sender.output_fps(),
+                        reachable: status.reachable,
+                        last_error: status.last_error,
+                        power_limited: status.power_limited,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// `/start` and `/stop` act on all pipelines when `pipeline` is missing
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct PipelineRequest {
+    pipeline: Option<String>,
+}
+
+/// `null` switches back to pipelines without any profile
+#[derive(Deserialize)]
+struct ProfileRequest {
+    profile: Option<String>,
+}
+
+/// `null` goes back to brightness of the config
+#[derive(Deserialize)]
+struct BrightnessRequest {
+    brightness: Option<f64>,
+}
+
+/// Saved into the config file
+#[derive(Deserialize)]
+struct MasterBrightnessRequest {
+    master_brightness: f64,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+type ApiResult<T> = Result<T, (u16, String)>;
+
+/// HTTP control API, every request is turned into a `Message` for the main loop and
+/// answered with the status which follows it. Live preview of the LEDs and metrics for
+/// Prometheus are served too.
+pub struct ApiServer {
+    server: Arc<Server>,
+    thread: Option<JoinHandle<()>>,
+    /// Cleared on stop, ends preview streams
+    is_running: Arc<AtomicBool>,
+}
+
+impl ApiServer {
+    pub fn spawn(
+        address: &str,
+        tx: Sender<Message>,
+        preview: Arc<Preview>,
+        metrics: Arc<Metrics>,
+    ) -> Result<Self, String> {
+        let server = Server::http(address)
+            .map_err(|err| format!("Could not start HTTP API on {}: {}", address, err))?;
+        let server = Arc::new(server);
+        let is_running = Arc::new(AtomicBool::new(true));
+        info!("HTTP API listening on http://{}", address);
+        info!("LED preview on http://{}/preview", address);
+        info!("Prometheus metrics on http://{}/metrics", address);
+
+        let thread = {
+            let server = server.clone();
+            let is_running = is_running.clone();
+            spawn(move || {
+                for request in server.incoming_requests() {
+                    match Endpoint::of(request.url()) {
+                        Endpoint::PreviewPage => respond(
+                            request,
+                            200,
+                            preview::PAGE.to_string(),
+                            "text/html; charset=utf-8",
+                        ),
+                        Endpoint::Metrics => {
+                            respond(request, 200, metrics.render(), "text/plain; version=0.0.4")
+                        }
+                        // every viewer gets its own thread, streams would block other requests
+                        Endpoint::PreviewStream => {
+                            let preview = preview.clone();
+                            let is_running = is_running.clone();
+                            spawn(move || preview.stream(request, &is_running));
+                        }
+                        Endpoint::Api => handle(request, &tx),
+                    }
+                }
+            })
+        };
+
+        Ok(Self {
+            server,
+            thread: Some(thread),
+            is_running,
+        })
+    }
+
+    pub fn stop(&mut self) {
+        self.is_running.store(false, Ordering::Relaxed);
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            thread.join().expect("could not stop HTTP API");
+        }
+    }
+}
+
+/// Requests served apart from the JSON API
+#[derive(Debug, PartialEq, Eq)]
+enum Endpoint {
+    PreviewPage,
+    PreviewStream,
+    Metrics,
+    Api,
+}
+
+impl Endpoint {
+    fn of(url: &str) -> Self {
+        match path(url) {
+            "/preview" => Endpoint::PreviewPage,
+            "/preview/ws" => Endpoint::PreviewStream,
+            "/metrics" => Endpoint::Metrics,
+            _ => Endpoint::Api,
+        }
+    }
+}
+
+/// `url` without its query and trailing slash
+fn path(url: &str) -> &str {
+    let path = url.split('?').next().unwrap_or_default();
+    match path.strip_suffix('/') {
+        Some(trimmed) if !trimmed.is_empty() => trimmed,
+        _ => path,
+    }
+}
+
+fn handle(mut request: Request, tx: &Sender<Message>) {
+    let mut body = String::new();
+    let (code, json) = match request.as_reader().read_to_string(&mut body) {
+        Ok(_) => answer(request.method(), request.url(), &body, tx),
+        Err(err) => error_json(400, format!("could not read body: {}", err)),
+    };
+    respond(request, code, json, "application/json");
+}
+
+/// Status code and JSON body answering an API request, the status of the app or an error
+fn answer(method: &Method, url: &str, body: &str, tx: &Sender<Message>) -> (u16, String) {
+    match route(method, url, body, tx) {
+        Ok(status) => (
+            200,
+            serde_json::to_string(&status).expect("status is always serializable"),
+        ),
+        Err((code, error)) => error_json(code, error),
+    }
+}
+
+fn error_json(code: u16, error: String) -> (u16, String) {
+    let json = serde_json::to_string(&ErrorResponse { error });
+    (code, json.expect("errors are always serializable"))
+}
+
+fn respond(request: Request, code: u16, body: String, content_type: &str) {
+    let response = Response::from_string(body)
+        .with_status_code(code)
+        .with_header(Header::from_bytes("Content-Type", content_type).expect("header is valid"));
+    if let Err(err) = request.respond(response) {
+        warn!("Could not answer HTTP API request: {}", err);
+    }
+}
+
+fn route(method: &Method, url: &str, body: &str, tx: &Sender<Message>) -> ApiResult<Status> {
+    let path = path(url);
+
+    match (method, path) {
+        (Method::Get, "/status") => {}
+        (Method::Post, "/start") => {
+            let message = match parse::<PipelineRequest>(body)?.pipeline {
+                Some(name) => Message::Start(known_pipeline(tx, name)?),
+                None => Message::StartAll,
+            };
+            send(tx, message)?;
+        }
+        (Method::Post, "/stop") => {
+            let message = match parse::<PipelineRequest>(body)?.pipeline {
+                Some(name) => Message::Stop(known_pipeline(tx, name)?),
+                None => Message::StopAll,
+            };
+            send(tx, message)?;
+        }
+        (Method::Post, "/profile") => {
+            let profile = parse::<ProfileRequest>(body)?.profile;
+            if let Some(name) = &profile {
+                if !status(tx)?.profiles.contains(name) {
+                    return Err((404, format!("there is no profile `{}`", name)));
+                }
+            }
+            send(tx, Message::SetProfile(profile))?;
+        }
+        (Method::Post, "/brightness") => {
+            let brightness = parse::<BrightnessRequest>(body)?.brightness;
+            if let Some(brightness) = brightness {
+                if !MULTIPLIER_RANGE.contains(&brightness) {
+                    return Err((
+                        400,
+                        format!(
+                            "brightness has to be in {:.1}-{:.1}, found {}",
+                            MULTIPLIER_RANGE.start(),
+                            MULTIPLIER_RANGE.end(),
+                            brightness
+                        ),
+                    ));
+                }
+            }
+            send(tx, Message::SetBrightness(brightness))?;
+        }
+        (Method::Post, "/master_brightness") => {
+            let master_brightness = parse::<MasterBrightnessRequest>(body)?.master_brightness;
+            if !MASTER_BRIGHTNESS_RANGE.contains(&master_brightness) {
+                return Err((
+                    400,
+                    format!(
+                        "master_brightness has to be in {:.1}-{:.1}, found {}",
+                        MASTER_BRIGHTNESS_RANGE.start(),
+                        MASTER_BRIGHTNESS_RANGE.end(),
+                        master_brightness
+                    ),
+                ));
+            }
+            if let Some(variable) = status(tx)?.master_brightness_variable {
+                return Err((
+                    409,
+                    format!(
+                        "master_brightness is set by environment variable {}, unset it to change \
+                         master brightness",
+                        variable
+                    ),
+                ));
+            }
+            send(tx, Message::SetMasterBrightness(master_brightness))?;
+        }
+        (_, "/status" | "/start" | "/stop" | "/profile" | "/brightness" | "/master_brightness") => {
+            return Err((405, format!("{} is not allowed on {}", method, path)));
+        }
+        _ => return Err((404, format!("there is no endpoint {}", path))),
+    }
+
+    // messages are handled in order, so the status already reflects the command
+    status(tx)
+}
+
+/// Empty body is the same as an empty JSON object
+fn parse<T: DeserializeOwned>(body: &str) -> ApiResult<T> {
+    let body = if body.trim().is_empty() { "{}" } else { body };
+    serde_json::from_str(body).map_err(|err| (400, format!("invalid body: {}", err)))
+}
+
+fn known_pipeline(tx: &Sender<Message>, name: String) -> ApiResult<String> {
+    if status(tx)?.pipelines.iter().any(|p| p.name == name) {
+        Ok(name)
+    } else {
+        Err((404, format!("there is no pipeline `{}`", name)))
+    }
+}
+
+fn send(tx: &Sender<Message>, message: Message) -> ApiResult<()> {
+    tx.send(message)
+        .map_err(|_| (503, "app is shutting down".to_string()))
+}
+
+fn status(tx: &Sender<Message>) -> ApiResult<Status> {
+    let (reply_tx, reply_rx) = mpsc::channel();
+    send(tx, Message::Status(reply_tx))?;
+    reply_rx
+        .recv_timeout(REPLY_TIMEOUT)
+        .map_err(|_| (503, "app did not answer in time".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    fn app() -> (Sender<Message>, Arc<Mutex<Vec<Message>>>) {
+        app_with_variable(None)
+    }
+
+    /// Main loop answering status requests, every other message is kept for the test.
+    /// `variable` overrides master brightness like `WLED_AMBILIGHT_MASTER_BRIGHTNESS`.
+    fn app_with_variable(
+        variable: Option<&'static str>,
+    ) -> (Sender<Message>, Arc<Mutex<Vec<Message>>>) {
+        let (tx, rx) = mpsc::channel();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        {
+            let received = received.clone();
+            spawn(move || {
+                for message in rx {
+                    match message {
+                        Message::Status(reply) => {
+                            let _ = reply.send(Status {
+                                running: true,
+                                profile: None,
+                                profiles: vec!["Movies".to_string()],
+                                brightness: None,
+                                master_brightness: 1.0,
+                                master_brightness_variable: variable.map(str::to_string),
+                                pipelines: vec![PipelineStatus {
+                                    name: "Main display".to_string(),
+                                    running: true,
+                                    state: PipelineState::Running,
+                                    target: "127.0.0.1".to_string(),
+                                    fps: 60.0,
+                                    output_fps: 60.0,
+                                    target_output_fps: 60,
+                                    reachable: true,
+                                    last_error: None,
+                                    power_limited: false,
+                                }],
+                            });
+                        }
+                        message => received.lock().unwrap().push(message),
+                    }
+                }
+            });
+        }
+        (tx, received)
+    }
+
+    fn error_of(json: &str) -> String {
+        let value: serde_json::Value = serde_json::from_str(json).unwrap();
+        value["error"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn unknown_endpoint_is_not_found() {
+        let (tx, _) = app();
+        let (code, json) = answer(&Method::Get, "/nothing", "", &tx);
+        assert_eq!(code, 404);
+        assert_eq!(error_of(&json), "there is no endpoint /nothing");
+    }
+
+    #[test]
+    fn wrong_method_is_not_allowed() {
+        let (tx, received) = app();
+        assert_eq!(answer(&Method::Get, "/start", "", &tx).0, 405);
+        assert_eq!(answer(&Method::Post, "/status", "", &tx).0, 405);
+        assert_eq!(answer(&Method::Delete, "/brightness", "", &tx).0, 405);
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_body_is_bad_request() {
+        let (tx, received) = app();
+        let (code, json) = answer(&Method::Post, "/brightness", "{", &tx);
+        assert_eq!(code, 400);
+        assert!(error_of(&json).starts_with("invalid body"));
+        let (code, _) = answer(&Method::Post, "/brightness", r#"{"brightness":"x"}"#, &tx);
+        assert_eq!(code, 400);
+        // master brightness has no default to go back to
+        let (code, _) = answer(&Method::Post, "/master_brightness", "", &tx);
+        assert_eq!(code, 400);
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn values_out_of_range_are_rejected() {
+        let (tx, received) = app();
+        let (code, json) = answer(&Method::Post, "/brightness", r#"{"brightness":4.5}"#, &tx);
+        assert_eq!(code, 400);
+        assert_eq!(
+            error_of(&json),
+            "brightness has to be in 0.0-4.0, found 4.5"
+        );
+        let body = r#"{"master_brightness":-0.1}"#;
+        assert_eq!(
+            answer(&Method::Post, "/master_brightness", body, &tx).0,
+            400
+        );
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn master_brightness_set_by_environment_is_a_conflict() {
+        let (tx, received) = app_with_variable(Some("WLED_AMBILIGHT_MASTER_BRIGHTNESS"));
+        let body = r#"{"master_brightness":0.5}"#;
+        let (code, json) = answer(&Method::Post, "/master_brightness", body, &tx);
+        assert_eq!(code, 409);
+        assert!(error_of(&json).contains("WLED_AMBILIGHT_MASTER_BRIGHTNESS"));
+        // brightness of the API is not saved, so it does not fight the variable
+        let body = r#"{"brightness":0.5}"#;
+        assert_eq!(answer(&Method::Post, "/brightness", body, &tx).0, 200);
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn unknown_pipeline_and_profile_are_not_found() {
+        let (tx, received) = app();
+        let body = r#"{"pipeline":"Second display"}"#;
+        assert_eq!(answer(&Method::Post, "/start", body, &tx).0, 404);
+        let body = r#"{"profile":"Sports"}"#;
+        assert_eq!(answer(&Method::Post, "/profile", body, &tx).0, 404);
+        assert!(received.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn endpoints_are_matched_on_path_only() {
+        assert_eq!(Endpoint::of("/preview"), Endpoint::PreviewPage);
+        assert_eq!(Endpoint::of("/preview/"), Endpoint::PreviewPage);
+        assert_eq!(
+            Endpoint::of("/preview?pipeline=Main"),
+            Endpoint::PreviewPage
+        );
+        assert_eq!(Endpoint::of("/preview/ws?v=2"), Endpoint::PreviewStream);
+        assert_eq!(Endpoint::of("/metrics/"), Endpoint::Metrics);
+        assert_eq!(Endpoint::of("/previews"), Endpoint::Api);
+        assert_eq!(Endpoint::of("/"), Endpoint::Api);
+        assert_eq!(path("/status/?pretty"), "/status");
+        assert_eq!(path("/"), "/");
+    }
+
+    #[test]
+    fn command_is_sent_and_answered_with_status() {
+        let (tx, received) = app();
+        let body = r#"{"pipeline":"Main display"}"#;
+        let (code, json) = answer(&Method::Post, "/stop?verbose", body, &tx);
+        assert_eq!(code, 200);
+        let status: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(status["pipelines"][0]["name"], "Main display");
+
+        let (code, _) = answer(&Method::Post, "/brightness", r#"{"brightness":null}"#, &tx);
+        assert_eq!(code, 200);
+
+        let received = received.lock().unwrap();
+        assert!(matches!(&received[..], [
+            Message::Stop(name),
+            Message::SetBrightness(None),
+        ] if name == "Main display"));
+    }
+}
@@ -0,0 +1,133 @@
+use std::{env, path::PathBuf};
+
+use crate::config::MASTER_BRIGHTNESS_RANGE;
+
+const USAGE: &str = "Usage: wled_ambilight [OPTIONS]
+       wled_ambilight render <FRAMES_DIR> [OPTIONS]
+
+Commands:
+  render              run a folder of images (png, jpg, bmp) through color extraction and
+                      processing of one pipeline, frames are taken in file name order,
+                      e.g. extracted from a video by `ffmpeg -i movie.mkv frames/%05d.png`
+
+Render options:
+  --pipeline <NAME>   pipeline to render, the first one by default
+  --timeline <PATH>   LED colors of every frame, CSV or JSON by extension (timeline.csv)
+  --strip <PATH>      PNG with a column per frame and a row per LED (strip.png)
+  --config and --profile select settings the same way as when streaming
+
+Options:
+  --config <PATH>     config file to use instead of looking it up
+  --profile <NAME>    profile selected on start, overrides active_profile
+  --headless          run without tray icon and start streaming immediately,
+                      always on outside of Windows, SIGINT/SIGTERM stop it cleanly
+  --target <HOST>     send every pipeline to this WLED host, overrides wled_ip
+  --dry-run           capture and process colors without sending them
+  --master-brightness <0.0-1.0>
+                      dim every pipeline, the value is saved into the config
+  --log-file <PATH>   append log to this file instead of printing it
+  --record <DIR>      record frames sent by every pipeline into this directory
+  --replay <PATH>     send frames of a recording instead of capturing the screen,
+                      recorded layout has to match the layout of every pipeline
+  --help              print this help
+
+Config file is looked up in this order:
+  --config, WLED_AMBILIGHT_CONFIG environment variable,
+  user config directory (%APPDATA%\\WLED Ambilight or $XDG_CONFIG_HOME/wled-ambilight),
+  directory of the executable. Missing file is created in the user config directory.
+
+Config fields can be overridden by WLED_AMBILIGHT_<FIELD> environment variables, pipeline
+fields apply to every pipeline and win over the active profile. Nested fields are separated
+by double underscore, e.g. WLED_AMBILIGHT_ADJUSTMENTS__BRIGHTNESS=0.5 or
+WLED_AMBILIGHT_API__ADDRESS=0.0.0.0:8585";
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub headless: bool,
+    pub target: Option<String>,
+    pub dry_run: bool,
+    pub master_brightness: Option<f64>,
+    pub log_file: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    /// Folder of frames rendered offline by the `render` command instead of streaming
+    pub render: Option<PathBuf>,
+    pub pipeline: Option<String>,
+    pub timeline: Option<PathBuf>,
+    pub strip: Option<PathBuf>,
+}
+
+impl Args {
+    /// Parses arguments of the process, prints usage and exits on `--help`
+    pub fn parse() -> Result<Self, String> {
+        let mut args = Args::default();
+        let mut arguments = env::args().skip(1).peekable();
+
+        if arguments.peek().map(String::as_str) == Some("render") {
+            arguments.next();
+            let frames = arguments
+                .next()
+                .filter(|frames| !frames.starts_with("--"))
+                .ok_or_else(|| format!("render needs a folder of frames\n\n{}", USAGE))?;
+            args.render = Some(PathBuf::from(frames));
+        }
+
+        while let Some(argument) = arguments.next() {
+            let mut value = |name: &str| {
+                arguments
+                    .next()
+                    .ok_or_else(|| format!("{} needs a value\n\n{}", name, USAGE))
+            };
+
+            match argument.as_str() {
+                "--config" => args.config = Some(PathBuf::from(value("--config")?)),
+                "--profile" => args.profile = Some(value("--profile")?),
+                "--headless" => args.headless = true,
+                "--target" => args.target = Some(value("--target")?),
+                "--dry-run" => args.dry_run = true,
+                "--master-brightness" => {
+                    let master_brightness = value("--master-brightness")?;
+                    args.master_brightness = Some(
+                        master_brightness
+                            .parse()
+                            .ok()
+                            .filter(|value| MASTER_BRIGHTNESS_RANGE.contains(value))
+                            .ok_or_else(|| {
+                                format!(
+                                    "--master-brightness has to be in 0.0-1.0, found `{}`",
+                                    master_brightness
+                                )
+                            })?,
+                    );
+                }
+                "--log-file" => args.log_file = Some(PathBuf::from(value("--log-file")?)),
+                "--record" => args.record = Some(PathBuf::from(value("--record")?)),
+                "--replay" => args.replay = Some(PathBuf::from(value("--replay")?)),
+                "--pipeline" => args.pipeline = Some(value("--pipeline")?),
+                "--timeline" => args.timeline = Some(PathBuf::from(value("--timeline")?)),
+                "--strip" => args.strip = Some(PathBuf::from(value("--strip")?)),
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => return Err(format!("unknown argument `{}`\n\n{}", argument, USAGE)),
+            }
+        }
+
+        if args.render.is_none()
+            && (args.pipeline.is_some() || args.timeline.is_some() || args.strip.is_some())
+        {
+            return Err(format!(
+                "--pipeline, --timeline and --strip only work with render\n\n{}",
+                USAGE
+            ));
+        }
+
+        // there is no tray icon outside of Windows
+        args.headless |= cfg!(not(windows));
+
+        Ok(args)
+    }
+}
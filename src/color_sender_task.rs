@@ -1,89 +1,1039 @@
 use std::{
     net::UdpSocket,
+    path::PathBuf,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        mpsc::Sender,
+        Arc, Mutex,
     },
-    thread::{spawn, JoinHandle},
+    thread::{scope, sleep, spawn, JoinHandle, ScopedJoinHandle},
     time::{Duration, Instant},
 };
 
-use crate::{config::Config, screen::Screen};
+use log::{error, info, warn};
+use serde::Serialize;
+
+use crate::{
+    config::{PipelineConfig, WledType},
+    error::{Error, Result},
+    fallback::Fallback,
+    metrics::{Metrics, PipelineMetrics},
+    pacing::FramePacer,
+    preview::Preview,
+    processing::{ColorProcessor, MasterBrightness},
+    recording::Recorder,
+    screen::{Replay, Screen, Source},
+    standby::{apply_preset, StandbyChange, StandbyDetector},
+    Message,
+};
+
+/// How often effective FPS is recalculated
+const FPS_PERIOD: Duration = Duration::from_secs(1);
+/// WLED goes back to its own effect when no packet arrives for this many seconds
+const REALTIME_TIMEOUT_SECONDS: u8 = 5;
+/// Unchanged frames are sent again after this long, a lost keepalive still leaves a few more
+/// before the realtime timeout
+const KEEPALIVE_PERIOD: Duration = Duration::from_secs(1);
+/// First retry after a transient error, every further failed attempt doubles the wait
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Stop requests are noticed this fast while waiting for a retry
+const STOP_CHECK_PERIOD: Duration = Duration::from_millis(100);
+
+/// Settings and shared state every pipeline gets, no matter its config
+#[derive(Clone)]
+pub struct SenderContext {
+    /// Colors are captured and processed but never sent
+    pub dry_run: bool,
+    pub preview: Arc<Preview>,
+    pub metrics: Arc<Metrics>,
+    /// Directory every pipeline records its outgoing frames into
+    pub record: Option<PathBuf>,
+    /// Recording played by every pipeline instead of capturing the screen
+    pub replay: Option<PathBuf>,
+    /// Main loop, learns about pipelines which stopped on an error
+    pub tx: Sender<Message>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PipelineState {
+    #[default]
+    Stopped,
+    Running,
+    /// Waiting before another attempt after a transient error
+    Retrying,
+    /// Stopped on an error which won't go away on its own, see `last_error`
+    Failed,
+    /// Screen is black or unchanged, nothing is sent until it changes
+    Standby,
+}
+
+/// Health of a pipeline as reported by its thread
+#[derive(Debug, Clone, Default)]
+pub struct SenderStatus {
+    pub state: PipelineState,
+    /// Frames captured per second
+    pub fps: f32,
+    /// Frames sent (or skipped as unchanged) per second, `output_fps` of the config is the target
+    pub output_fps: f32,
+    /// Last frame was sent without an error, UDP can not tell whether WLED received it
+    pub reachable: bool,
+    pub last_error: Option<String>,
+    /// Power limiter dimmed the last frame to stay within the PSU budget
+    pub power_limited: bool,
+}
 
 pub struct ColorSenderTask {
     is_running: Arc<AtomicBool>,
     thread: Option<JoinHandle<()>>,
-    config: Config,
+    config: PipelineConfig,
+    /// Settings picked up by the running thread before the next frame
+    pending_config: Arc<Mutex<Option<PipelineConfig>>>,
+    context: SenderContext,
+    status: Arc<Mutex<SenderStatus>>,
 }
 
 impl ColorSenderTask {
-    pub fn new(config: Config) -> Self {
+    pub fn new(config: PipelineConfig, context: SenderContext) -> Self {
         Self {
             is_running: Arc::new(AtomicBool::new(false)),
             thread: None,
             config,
+            pending_config: Arc::new(Mutex::new(None)),
+            context,
+            status: Arc::new(Mutex::new(SenderStatus::default())),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Thread of a pipeline which failed has ended, the pipeline is not running anymore.
+    /// A stopping pipeline is not running either, even while it still fades out.
+    pub fn is_running(&self) -> bool {
+        self.is_running.load(Ordering::Relaxed)
+            && self
+                .thread
+                .as_ref()
+                .is_some_and(|thread| !thread.is_finished())
+    }
+
+    /// Rate colors are sent at, `SenderStatus::output_fps` is the rate actually reached
+    pub fn output_fps(&self) -> u32 {
+        self.config.output_fps
+    }
+
+    /// Host colors are sent to
+    pub fn target(&self) -> &str {
+        &self.config.wled_ip
+    }
+
+    /// File of the LUT, already resolved against the config directory
+    pub fn lut_path(&self) -> Option<PathBuf> {
+        self.config.lut.as_ref().map(|lut| PathBuf::from(&lut.path))
+    }
+
+    pub fn status(&self) -> SenderStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Applies new settings to the running thread, only the capture starts over when its
+    /// settings changed, so WLED stays in realtime mode. A failed pipeline is started again,
+    /// new settings may be what it was missing.
+    pub fn update(&mut self, config: PipelineConfig) {
+        if config == self.config {
+            return;
+        }
+
+        *self.pending_config.lock().unwrap() = Some(config.clone());
+        self.config = config;
+        // the thread ended without being asked to stop
+        if self.is_running.load(Ordering::Relaxed) && !self.is_running() {
+            self.start();
+        }
+    }
+
+    /// Loads the LUT again when its file is one of `changed_paths`, the config stays the same
+    pub fn reload_lut(&mut self, changed_paths: &[PathBuf]) {
+        let uses_changed = self
+            .lut_path()
+            .is_some_and(|path| changed_paths.contains(&path));
+        if uses_changed && self.is_running() {
+            *self.pending_config.lock().unwrap() = Some(self.config.clone());
         }
     }
 
     pub fn start(&mut self) {
+        if self.is_running() {
+            return;
+        }
+        // joins the thread of a failed pipeline or one which is still fading out
+        self.stop();
+        self.status.lock().unwrap().state = PipelineState::Stopped;
         self.is_running.store(true, Ordering::Relaxed);
+        // the thread starts with current config, older pending changes are already in it
+        self.pending_config.lock().unwrap().take();
 
-        let wled_ip = self.config.wled_ip.clone();
-        let mut screen = Screen::new(self.config.clone());
-        let is_running = self.is_running.clone();
+        let metrics = self.context.metrics.pipeline(&self.config.name);
+        metrics.brightness.set(self.config.adjustments.brightness);
+        metrics.master_brightness.set(self.config.master_brightness);
+        let mut worker = Worker {
+            processor: ColorProcessor::new(&self.config),
+            master_brightness: MasterBrightness::new(self.config.master_brightness),
+            config: self.config.clone(),
+            replay: self.context.replay.clone(),
+            record: self.context.record.clone(),
+            recorder: None,
+            metrics,
+            is_running: self.is_running.clone(),
+            pending_config: self.pending_config.clone(),
+            status: self.status.clone(),
+            preview: self.context.preview.clone(),
+            dry_run: self.context.dry_run,
+            connected_before: false,
+            sent_frames: false,
+            first_frame_at: None,
+        };
+        let status = self.status.clone();
+        let tx = self.context.tx.clone();
         self.thread = Some(spawn(move || {
-            let mut frame_count = 0;
-            let mut start = Instant::now();
+            let mut backoff = INITIAL_BACKOFF;
+            worker.start_recording();
 
-            while is_running.load(Ordering::Relaxed) {
-                let Ok(socket) = UdpSocket::bind("0.0.0.0:0") else { 
-                    println!("Could not create UDP socket, trying again in 2 seconds");
-                    std::thread::sleep(Duration::from_secs(2));
-                    continue;
+            while worker.is_running.load(Ordering::Relaxed) {
+                let previous =
+                    std::mem::replace(&mut status.lock().unwrap().state, PipelineState::Running);
+                if previous == PipelineState::Retrying {
+                    let _ = tx.send(Message::StateChanged);
+                }
+                worker.sent_frames = false;
+                let result = worker.run();
+                // a pipeline which worked for a while starts over with short waits
+                if worker.sent_frames {
+                    backoff = INITIAL_BACKOFF;
+                }
+
+                match result {
+                    Ok(()) => {}
+                    Err(err) if err.is_transient() => {
+                        warn!(
+                            pipeline = worker.config.name;
+                            "{}, trying again in {} s", err, backoff.as_secs()
+                        );
+                        set_error(&status, PipelineState::Retrying, &err);
+                        // the receiver is gone only after the main loop ended
+                        let _ = tx.send(Message::StateChanged);
+                        worker.sleep_while_running(backoff);
+                        backoff = (backoff * 2).min(MAX_BACKOFF);
+                    }
+                    Err(err) => {
+                        error!(pipeline = worker.config.name; "{}, stopping", err);
+                        set_error(&status, PipelineState::Failed, &err);
+                        // the receiver is gone only after the main loop ended
+                        let _ = tx.send(Message::Failed {
+                            pipeline: worker.config.name.clone(),
+                            error: err.to_string(),
+                        });
+                        break;
+                    }
+                }
+            }
+
+            worker.preview.clear(&worker.config.name);
+            worker.metrics.fps.set(0.0);
+            worker.metrics.output_fps.set(0.0);
+            worker.metrics.power_limited.set(0.0);
+            let mut status = status.lock().unwrap();
+            if status.state != PipelineState::Failed {
+                status.state = PipelineState::Stopped;
+            }
+            status.fps = 0.0;
+            status.output_fps = 0.0;
+            status.reachable = false;
+            status.power_limited = false;
+        }));
+    }
+
+    /// Asks the thread to fade out, release WLED and end, without waiting for it. Stopping
+    /// several pipelines this way first lets them fade out at the same time.
+    pub fn request_stop(&mut self) {
+        self.is_running.store(false, Ordering::Relaxed);
+    }
+
+    /// Waits until the thread ended, a failed pipeline stays `Failed` with its error until it
+    /// is started again
+    pub fn stop(&mut self) {
+        self.request_stop();
+        if let Some(thread) = self.thread.take() {
+            thread.join().expect("could not stop background job");
+        }
+        let mut status = self.status.lock().unwrap();
+        if status.state != PipelineState::Failed {
+            status.state = PipelineState::Stopped;
+        }
+    }
+}
+
+fn set_error(status: &Mutex<SenderStatus>, state: PipelineState, err: &Error) {
+    let mut status = status.lock().unwrap();
+    status.state = state;
+    status.reachable = false;
+    status.last_error = Some(err.to_string());
+}
+
+/// Captured colors, not processed yet unless they come from a recording
+struct CapturedFrame {
+    colors: Vec<u8>,
+    captured_at: Instant,
+}
+
+/// Latest two captured frames, the capture thread replaces them as fast as it captures and
+/// the output takes whatever is newest when it is due
+#[derive(Default)]
+struct FrameSlot {
+    /// Previous and latest frame
+    frames: Mutex<(Option<CapturedFrame>, Option<CapturedFrame>)>,
+}
+
+impl FrameSlot {
+    fn put(&self, frame: CapturedFrame) {
+        let mut frames = self.frames.lock().unwrap();
+        frames.0 = frames.1.replace(frame);
+    }
+
+    fn clear(&self) {
+        *self.frames.lock().unwrap() = (None, None);
+    }
+
+    /// Colors due at `now` and when the latest frame was captured, `None` before the first
+    /// frame. Interpolated colors blend from the previous into the latest frame over the time
+    /// between them, so they move smoothly but lag one capture period behind.
+    fn colors(&self, interpolate: bool, now: Instant) -> Option<(Vec<u8>, Instant)> {
+        let frames = self.frames.lock().unwrap();
+        let latest = frames.1.as_ref()?;
+        let colors = match &frames.0 {
+            Some(previous) if interpolate => {
+                let period = latest.captured_at - previous.captured_at;
+                let progress = if period.is_zero() {
+                    1.0
+                } else {
+                    (now.saturating_duration_since(latest.captured_at)
+                        .as_secs_f32()
+                        / period.as_secs_f32())
+                    .min(1.0)
                 };
+                previous
+                    .colors
+                    .iter()
+                    .zip(&latest.colors)
+                    .map(|(&from, &to)| {
+                        (from as f32 + (to as f32 - from as f32) * progress).round() as u8
+                    })
+                    .collect()
+            }
+            _ => latest.colors.clone(),
+        };
+        Some((colors, latest.captured_at))
+    }
+}
 
-                if socket.connect(format!("{}:21324", wled_ip)).is_err() {
-                    println!("Could not connnect to WLED, trying again in 2 seconds");
-                    std::thread::sleep(Duration::from_secs(2));
-                    continue;
+/// State of the pipeline thread, kept across retries
+struct Worker {
+    config: PipelineConfig,
+    processor: ColorProcessor,
+    master_brightness: MasterBrightness,
+    /// Frames come from this recording instead of the screen, they are already processed
+    replay: Option<PathBuf>,
+    record: Option<PathBuf>,
+    recorder: Option<Recorder>,
+    metrics: Arc<PipelineMetrics>,
+    is_running: Arc<AtomicBool>,
+    pending_config: Arc<Mutex<Option<PipelineConfig>>>,
+    status: Arc<Mutex<SenderStatus>>,
+    preview: Arc<Preview>,
+    dry_run: bool,
+    connected_before: bool,
+    /// At least one frame went through since the last attempt started
+    sent_frames: bool,
+    /// Colors fade in once after the pipeline starts, not after every retry or new target,
+    /// timed from the first frame sent since capture and connecting may take a while
+    first_frame_at: Option<Instant>,
+}
+
+impl Worker {
+    /// Streams colors until the pipeline is stopped or its target changes
+    fn run(&mut self) -> Result<()> {
+        let socket = self.connect()?;
+        if self.connected_before {
+            self.metrics.reconnects.increment();
+        }
+        self.connected_before = true;
+
+        self.stream(socket.as_ref())?;
+        // WLED is released only when it is left for good, errors are retried with it
+        release_realtime_mode(&self.config.name, socket.as_ref());
+        Ok(())
+    }
+
+    fn connect(&self) -> Result<Option<UdpSocket>> {
+        if self.dry_run {
+            info!(
+                pipeline = self.config.name;
+                "Dry run, frames are not sent to {}", self.config.wled_ip
+            );
+            return Ok(None);
+        }
+
+        let output_error = |error| Error::Output {
+            host: self.config.wled_ip.clone(),
+            error,
+        };
+        let socket = UdpSocket::bind("0.0.0.0:0").map_err(output_error)?;
+        socket
+            .connect((self.config.wled_ip.as_str(), self.config.wled_port))
+            .map_err(output_error)?;
+        Ok(Some(socket))
+    }
+
+    /// Captures on its own thread and sends at `output_fps` on this one, capture errors end
+    /// the output too
+    fn stream(&mut self, socket: Option<&UdpSocket>) -> Result<()> {
+        let slot = &FrameSlot::default();
+        let control = &CaptureControl {
+            capturing: AtomicBool::new(true),
+            restart: Mutex::new(None),
+        };
+        let replay = self.replay.clone();
+        let config = self.config.clone();
+        let metrics = self.metrics.clone();
+        let status = self.status.clone();
+
+        scope(|scope| {
+            let capture =
+                scope.spawn(move || capture(replay, config, slot, control, &metrics, &status));
+            let result = self.output(slot, control, socket, &capture);
+            control.capturing.store(false, Ordering::Relaxed);
+            let captured = capture.join().expect("capture thread panicked");
+            result.and(captured)
+        })
+    }
+
+    fn output(
+        &mut self,
+        slot: &FrameSlot,
+        control: &CaptureControl,
+        socket: Option<&UdpSocket>,
+        capture: &ScopedJoinHandle<Result<()>>,
+    ) -> Result<()> {
+        let mut reachable = false;
+        // empty until the first frame, which is always sent
+        let mut last_sent: Vec<u8> = Vec::new();
+        let mut last_sent_at = Instant::now();
+        let mut pacer = FramePacer::new(self.config.output_fps);
+        self.metrics.output_target_fps.set(pacer.target_fps());
+        let mut standby = self.config.standby.clone().map(StandbyDetector::new);
+        let mut fallback = self.fallback();
+        // age of the missing first frame, the fallback takes over when capture can't start
+        let output_start = Instant::now();
+
+        while self.is_running.load(Ordering::Relaxed) && !capture.is_finished() {
+            let pending_config = self.pending_config.lock().unwrap().take();
+            if let Some(config) = pending_config {
+                info!(pipeline = config.name; "Applying changed settings");
+                self.processor = ColorProcessor::new(&config);
+                self.metrics.brightness.set(config.adjustments.brightness);
+                self.metrics.master_brightness.set(config.master_brightness);
+                if config.master_brightness != self.master_brightness.brightness() {
+                    self.master_brightness = MasterBrightness::new(config.master_brightness);
+                }
+                let target_changed = config.wled_ip != self.config.wled_ip
+                    || config.wled_port != self.config.wled_port;
+                pacer.set_fps(config.output_fps);
+                self.metrics.output_target_fps.set(pacer.target_fps());
+                if config.standby != self.config.standby {
+                    standby = config.standby.clone().map(StandbyDetector::new);
+                    self.leave_standby();
+                    last_sent.clear();
+                }
+                let capture_changed = self.config.needs_restart(&config);
+                if capture_changed {
+                    info!(pipeline = config.name; "Capture settings changed, restarting capture");
+                    *control.restart.lock().unwrap() = Some(config.clone());
+                }
+                let fallback_changed = capture_changed || config.fallback != self.config.fallback;
+                self.config = config;
+                if fallback_changed {
+                    fallback = self.fallback();
+                }
+                if target_changed {
+                    return Ok(());
                 }
+            }
+
+            pacer.wait();
+            self.metrics.output_fps.set(pacer.actual_fps());
+            self.status.lock().unwrap().output_fps = pacer.actual_fps() as f32;
 
-                while is_running.load(Ordering::Relaxed) {
-                    frame_count += 1;
+            let now = Instant::now();
+            let (mut buffer, captured_at) = match slot.colors(self.config.interpolate, now) {
+                // frames of the old layout are left until the capture restarts with a new one
+                Some((colors, captured_at)) if colors.len() == self.led_count() * 4 => {
+                    (Some(colors), captured_at)
+                }
+                _ if fallback.is_some() => (None, output_start),
+                _ => continue,
+            };
+            let frame_age = now.saturating_duration_since(captured_at);
+            let stale = fallback
+                .as_ref()
+                .is_some_and(|fallback| fallback.is_stale(frame_age));
+            if buffer.is_none() && !stale {
+                continue;
+            }
 
-                    if frame_count % 120 == 0 {
-                        let duration = start.elapsed();
-                        println!("Fps: {}", (frame_count * 1000) / duration.as_millis());
-                        if duration > Duration::from_secs(5) {
-                            start = Instant::now();
-                            frame_count = 0;
-                        }
+            if let Some(standby) = &mut standby {
+                // a repeated stale frame says nothing about the screen
+                let change = match &buffer {
+                    Some(colors) if !stale => standby.update(colors, now),
+                    _ => None,
+                };
+                match change {
+                    Some(StandbyChange::Entered) => self.enter_standby(standby, socket),
+                    Some(StandbyChange::Left) => {
+                        info!(pipeline = self.config.name; "Screen changed, leaving standby");
+                        self.leave_standby();
+                        last_sent.clear();
                     }
+                    None => {}
+                }
+                if standby.is_active() {
+                    continue;
+                }
+            }
+            if self.replay.is_none() {
+                if let Some(colors) = &mut buffer {
+                    self.processor.adjust(colors);
+                }
+            }
+            let mut buffer = match &mut fallback {
+                Some(fallback) => fallback.apply(buffer, frame_age, now),
+                None => buffer.expect("frames are awaited without a fallback"),
+            };
+            // after the fallback, its effects must not go over the budget either
+            if self.replay.is_none() {
+                self.processor.limit(&mut buffer);
+                let power_limited = self.processor.is_power_limited();
+                let mut status = self.status.lock().unwrap();
+                if status.power_limited != power_limited {
+                    status.power_limited = power_limited;
+                    self.metrics.power_limited.set(power_limited as u8 as f64);
+                }
+            }
+            // recordings hold processed colors, replay fades them in and dims them again
+            let recorded = self.recorder.is_some().then(|| buffer.clone());
+            let fade_in = fade_in_level(&mut self.first_frame_at, now, self.config.fade_in_ms);
+            if fade_in < 1.0 {
+                for channel in &mut buffer {
+                    *channel = (*channel as f32 * fade_in).round() as u8;
+                }
+            }
+            // last stage, everything before works with full brightness
+            self.master_brightness.apply(&mut buffer);
+            if self.preview.is_watched() {
+                self.preview.publish(&self.config, &buffer);
+            }
 
-                    screen.wait_for_next_frame();
-                    if let Some(colors) = screen.get_border_colors() {
-                        let buffer = colors.concat();
+            if is_unchanged(&buffer, &last_sent, self.config.change_tolerance)
+                && last_sent_at.elapsed() < KEEPALIVE_PERIOD
+            {
+                self.metrics.frames_skipped.increment();
+                continue;
+            }
+            if let Some(recorded) = recorded {
+                self.record(&recorded);
+            }
 
-                        let mut wled_packet: Vec<u8> = Vec::new();
-                        wled_packet.reserve(buffer.len() + 2);
-                        // TODO use wled_type and send RGB or RGBW based on that
-                        wled_packet.extend_from_slice(&[3, 5]);
-                        wled_packet.extend_from_slice(&buffer);
+            self.sent_frames = true;
+            let Some(socket) = socket else {
+                last_sent = buffer;
+                last_sent_at = Instant::now();
+                continue;
+            };
+            self.send(socket, &buffer)?;
+            last_sent = buffer;
+            last_sent_at = Instant::now();
+            if !stale {
+                self.metrics.latency.observe(captured_at.elapsed());
+            }
+            if !reachable {
+                reachable = true;
+                let mut status = self.status.lock().unwrap();
+                status.reachable = true;
+                status.last_error = None;
+            }
+        }
 
-                        if socket.send(&wled_packet).is_err() {
-                            println!("Could not send a frame. Reconnecting...");
-                            break;
-                        }
-                    }
+        let standing_by = standby.as_ref().is_some_and(StandbyDetector::is_active);
+        if let (Some(socket), false) = (socket, standing_by) {
+            if !self.is_running.load(Ordering::Relaxed)
+                && self.config.fade_out_ms > 0
+                && !last_sent.is_empty()
+            {
+                self.fade_out(socket, &last_sent, &mut pacer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Ramps `from` down to `fade_out_color` at the output rate, so a stopped pipeline does not
+    /// leave the strip mid-frame before it is released
+    fn fade_out(&self, socket: &UdpSocket, from: &[u8], pacer: &mut FramePacer) -> Result<()> {
+        let [r, g, b] = self.config.fade_out_color;
+        let mut target = [r, g, b, 0].repeat(from.len() / 4);
+        // `from` was sent, so it is dimmed already
+        self.master_brightness.apply(&mut target);
+        let start = Instant::now();
+        loop {
+            pacer.wait();
+            let done = fade_level(start.elapsed(), self.config.fade_out_ms);
+            let colors: Vec<u8> = from
+                .iter()
+                .zip(&target)
+                .map(|(&from, &to)| (from as f32 + (to as f32 - from as f32) * done).round() as u8)
+                .collect();
+            self.send(socket, &colors)?;
+            if done >= 1.0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn send(&self, socket: &UdpSocket, colors: &[u8]) -> Result<()> {
+        let wled_packet = wled_packet(self.config.wled_type, colors);
+        if let Err(error) = socket.send(&wled_packet) {
+            self.metrics.send_errors.increment();
+            return Err(Error::Output {
+                host: self.config.wled_ip.clone(),
+                error,
+            });
+        }
+        self.metrics.frames_sent.increment();
+        Ok(())
+    }
+
+    fn led_count(&self) -> usize {
+        ((self.config.led_horizontal_count + self.config.led_vertical_count) * 2) as usize
+    }
+
+    fn fallback(&self) -> Option<Fallback> {
+        self.config
+            .fallback
+            .clone()
+            .map(|config| Fallback::new(&self.config.name, config, self.led_count()))
+    }
+
+    /// WLED is switched to the standby preset right away, without one it returns to its own
+    /// effect once the realtime timeout passes
+    fn enter_standby(&self, standby: &StandbyDetector, socket: Option<&UdpSocket>) {
+        let config = standby.config();
+        info!(
+            pipeline = self.config.name;
+            "Screen is black or unchanged for {} s, standing by", config.after_seconds
+        );
+        self.metrics.standbys.increment();
+        self.status.lock().unwrap().state = PipelineState::Standby;
+
+        let (Some(preset), Some(_)) = (config.preset, socket) else {
+            return;
+        };
+        // WLED ignores presets while it is in realtime mode
+        release_realtime_mode(&self.config.name, socket);
+        if let Err(err) = apply_preset(&self.config.wled_ip, preset) {
+            warn!(pipeline = self.config.name; "Could not switch WLED to preset {}: {}", preset, err);
+        }
+    }
+
+    fn leave_standby(&self) {
+        let mut status = self.status.lock().unwrap();
+        if status.state == PipelineState::Standby {
+            status.state = PipelineState::Running;
+        }
+    }
+
+    /// Recording is only a debugging aid, the pipeline runs without it when the file can't
+    /// be written
+    fn start_recording(&mut self) {
+        let Some(dir) = &self.record else {
+            return;
+        };
+        match Recorder::create(dir, &self.config) {
+            Ok((recorder, path)) => {
+                info!(pipeline = self.config.name; "Recording frames into {}", path.display());
+                self.recorder = Some(recorder);
+            }
+            Err(err) => {
+                warn!(pipeline = self.config.name; "Could not start recording: {}", err);
+            }
+        }
+    }
+
+    fn record(&mut self, colors: &[u8]) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        if let Err(err) = recorder.write(colors) {
+            warn!(pipeline = self.config.name; "Could not record a frame, stopping recording: {}", err);
+            self.recorder = None;
+        }
+    }
+
+    fn sleep_while_running(&self, duration: Duration) {
+        let start = Instant::now();
+        while self.is_running.load(Ordering::Relaxed) && start.elapsed() < duration {
+            sleep(STOP_CHECK_PERIOD.min(duration));
+        }
+    }
+}
+
+/// Lets the output stop the capture thread or make it start over with new settings
+struct CaptureControl {
+    capturing: AtomicBool,
+    /// Settings the capture is created again with before its next frame
+    restart: Mutex<Option<PipelineConfig>>,
+}
+
+impl CaptureControl {
+    fn should_stop(&self) -> bool {
+        !self.capturing.load(Ordering::Relaxed) || self.restart.lock().unwrap().is_some()
+    }
+}
+
+/// Captures on the calling thread until `capturing` is cleared, starting over whenever new
+/// settings come through `restart`. With a fallback effect transient errors are retried here
+/// instead, so the output keeps running and shows the effect rather than going down together
+/// with the capture.
+fn capture(
+    replay: Option<PathBuf>,
+    mut config: PipelineConfig,
+    slot: &FrameSlot,
+    control: &CaptureControl,
+    metrics: &Arc<PipelineMetrics>,
+    status: &Mutex<SenderStatus>,
+) -> Result<()> {
+    let capturing = &control.capturing;
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        if let Some(restart) = control.restart.lock().unwrap().take() {
+            config = restart;
+            slot.clear();
+            backoff = INITIAL_BACKOFF;
+        }
+        let mut captured_frames = false;
+        let result = capture_frames(
+            &replay,
+            &config,
+            slot,
+            control,
+            metrics,
+            status,
+            &mut captured_frames,
+        );
+        match result {
+            Ok(()) if capturing.load(Ordering::Relaxed) => {}
+            Err(err) if err.is_transient() && config.fallback.is_some() => {
+                if captured_frames {
+                    backoff = INITIAL_BACKOFF;
+                }
+                warn!(
+                    pipeline = config.name;
+                    "{}, capturing again in {} s", err, backoff.as_secs()
+                );
+                status.lock().unwrap().last_error = Some(err.to_string());
+                let start = Instant::now();
+                while !control.should_stop() && start.elapsed() < backoff {
+                    sleep(STOP_CHECK_PERIOD);
                 }
+                if !capturing.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
             }
-        }));
+            result => return result,
+        }
     }
+}
 
-    pub fn stop(&mut self) {
-        self.is_running.store(false, Ordering::Relaxed);
-        if let Some(thread) = self.thread.take() {
-            thread.join().expect("could not stop background job");
+/// Creates the capture source on the calling thread and puts its frames into `slot` until
+/// the output stops or restarts it
+fn capture_frames(
+    replay: &Option<PathBuf>,
+    config: &PipelineConfig,
+    slot: &FrameSlot,
+    control: &CaptureControl,
+    metrics: &Arc<PipelineMetrics>,
+    status: &Mutex<SenderStatus>,
+    captured_frames: &mut bool,
+) -> Result<()> {
+    let mut source = match replay {
+        Some(path) => Source::Replay(Replay::open(path, config)?),
+        None => Source::Screen(Box::new(Screen::new(config.clone(), metrics.clone())?)),
+    };
+    let mut frame_count = 0;
+    let mut fps_start = Instant::now();
+
+    while !control.should_stop() {
+        let elapsed = fps_start.elapsed();
+        if elapsed >= FPS_PERIOD {
+            let fps = frame_count as f64 / elapsed.as_secs_f64();
+            metrics.fps.set(fps);
+            status.lock().unwrap().fps = fps as f32;
+            fps_start = Instant::now();
+            frame_count = 0;
         }
+
+        source.wait_for_next_frame()?;
+        let captured_at = Instant::now();
+        let Some(colors) = source.get_border_colors()? else {
+            continue;
+        };
+        metrics.frames_captured.increment();
+        frame_count += 1;
+        *captured_frames = true;
+        slot.put(CapturedFrame {
+            colors: colors.concat(),
+            captured_at,
+        });
+    }
+
+    Ok(())
+}
+
+/// Every channel of `colors` is within `tolerance` of `last_sent`, layouts of different size
+/// always differ
+fn is_unchanged(colors: &[u8], last_sent: &[u8], tolerance: u8) -> bool {
+    colors.len() == last_sent.len()
+        && colors
+            .iter()
+            .zip(last_sent)
+            .all(|(color, last)| color.abs_diff(*last) <= tolerance)
+}
+
+/// Realtime UDP packet with RGBW `colors`, DRGB (protocol 2) for `WledType::Rgb` with white
+/// added to the other channels, DRGBW (protocol 3) for `WledType::Rgbw`
+fn wled_packet(wled_type: WledType, colors: &[u8]) -> Vec<u8> {
+    let bytes_per_led = match wled_type {
+        WledType::Rgb => 3,
+        WledType::Rgbw => 4,
+    };
+    let mut packet = Vec::with_capacity(colors.len() / 4 * bytes_per_led + 2);
+    packet.extend_from_slice(&[wled_type as u8, REALTIME_TIMEOUT_SECONDS]);
+    match wled_type {
+        WledType::Rgb => {
+            for color in colors.chunks_exact(4) {
+                packet.extend(
+                    color[..3]
+                        .iter()
+                        .map(|channel| channel.saturating_add(color[3])),
+                );
+            }
+        }
+        WledType::Rgbw => packet.extend_from_slice(colors),
+    }
+    packet
+}
+
+/// Share (0.0-1.0) of a fade lasting `duration_ms` done after `elapsed`
+fn fade_level(elapsed: Duration, duration_ms: u32) -> f32 {
+    if duration_ms == 0 {
+        return 1.0;
+    }
+    (elapsed.as_secs_f32() * 1000.0 / duration_ms as f32).min(1.0)
+}
+
+/// Share of the fade in done for a frame sent at `now`, the first frame starts the fade
+fn fade_in_level(first_frame_at: &mut Option<Instant>, now: Instant, duration_ms: u32) -> f32 {
+    fade_level(now - *first_frame_at.get_or_insert(now), duration_ms)
+}
+
+/// WLED keeps showing the last frame until its realtime timeout passes, a packet with timeout
+/// of 0 seconds gives the strip back to WLED right away
+fn release_realtime_mode(name: &str, socket: Option<&UdpSocket>) {
+    let Some(socket) = socket else {
+        return;
+    };
+    if socket.send(&[3, 0]).is_err() {
+        warn!(pipeline = name; "Could not release WLED realtime mode");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process, sync::mpsc::channel};
+
+    use super::*;
+    use crate::{config::ColorReducer, recording::Recorder};
+
+    /// Long enough for a loaded machine, tests finish as soon as what they wait for happens
+    const WAIT_LIMIT: Duration = Duration::from_secs(5);
+
+    /// Stops the task even when an assertion fails, so it doesn't keep sending
+    struct StopOnDrop(ColorSenderTask);
+
+    impl Drop for StopOnDrop {
+        fn drop(&mut self) {
+            self.0.stop();
+        }
+    }
+
+    /// Packets arriving at the WLED port up to the first one `done` accepts
+    fn receive_until(wled: &UdpSocket, mut done: impl FnMut(&[u8]) -> bool) -> Vec<Vec<u8>> {
+        let start = Instant::now();
+        let mut packets = Vec::new();
+        let mut buffer = [0; 2048];
+        while start.elapsed() < WAIT_LIMIT {
+            if let Ok(len) = wled.recv(&mut buffer) {
+                packets.push(buffer[..len].to_vec());
+                if done(&buffer[..len]) {
+                    return packets;
+                }
+            }
+        }
+        panic!(
+            "expected packet did not arrive, got {} others",
+            packets.len()
+        );
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(
+                start.elapsed() < WAIT_LIMIT,
+                "condition was not met in time"
+            );
+            sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn capture_settings_change_keeps_wled_in_realtime_mode() {
+        let wled = UdpSocket::bind("127.0.0.1:0").unwrap();
+        wled.set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        let config = PipelineConfig {
+            wled_ip: "127.0.0.1".to_string(),
+            wled_port: wled.local_addr().unwrap().port(),
+            fade_in_ms: 0,
+            fade_out_ms: 0,
+            ..Default::default()
+        };
+
+        let dir = std::env::temp_dir().join(format!("wled_ambilight_test_{}", process::id()));
+        let (mut recorder, recording) = Recorder::create(&dir, &config).unwrap();
+        let led_count = ((config.led_horizontal_count + config.led_vertical_count) * 2) as usize;
+        for frame in 0..5 {
+            recorder.write(&vec![frame * 40; led_count * 4]).unwrap();
+            sleep(Duration::from_millis(20));
+        }
+        drop(recorder);
+
+        let (tx, _rx) = channel();
+        let mut task = StopOnDrop(ColorSenderTask::new(
+            config.clone(),
+            SenderContext {
+                dry_run: false,
+                preview: Arc::default(),
+                metrics: Arc::default(),
+                record: None,
+                replay: Some(recording),
+                tx,
+            },
+        ));
+        task.0.start();
+        receive_until(&wled, |packet| packet[..2] == [3, 5]);
+
+        task.0.update(PipelineConfig {
+            max_fps: 30,
+            reducer: ColorReducer::Median,
+            ..config
+        });
+        wait_for(|| task.0.pending_config.lock().unwrap().is_none());
+        let mut received = 0;
+        let after = receive_until(&wled, |_| {
+            received += 1;
+            received == 10
+        });
+        assert!(after.iter().all(|packet| packet[..2] == [3, 5]));
+        assert!(task.0.is_running());
+
+        // the thread releases WLED on its own, nobody has to wait for it
+        task.0.request_stop();
+        assert!(!task.0.is_running());
+        receive_until(&wled, |packet| packet == [3, 0]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_pipeline_keeps_its_error_after_stop() {
+        let (tx, rx) = channel();
+        let mut task = StopOnDrop(ColorSenderTask::new(
+            PipelineConfig::default(),
+            SenderContext {
+                dry_run: true,
+                preview: Arc::default(),
+                metrics: Arc::default(),
+                record: None,
+                replay: Some(PathBuf::from("missing.wlrec")),
+                tx,
+            },
+        ));
+        task.0.start();
+        wait_for(|| !task.0.is_running());
+        assert!(matches!(rx.try_recv(), Ok(Message::Failed { .. })));
+
+        task.0.stop();
+        let status = task.0.status();
+        assert_eq!(status.state, PipelineState::Failed);
+        assert!(status.last_error.unwrap().contains("missing.wlrec"));
+    }
+
+    #[test]
+    fn fade_level_grows_over_duration() {
+        assert_eq!(fade_level(Duration::ZERO, 0), 1.0);
+        assert_eq!(fade_level(Duration::ZERO, 500), 0.0);
+        assert_eq!(fade_level(Duration::from_millis(125), 500), 0.25);
+        assert_eq!(fade_level(Duration::from_millis(500), 500), 1.0);
+        assert_eq!(fade_level(Duration::from_secs(10), 500), 1.0);
+    }
+
+    #[test]
+    fn fade_in_starts_with_first_frame() {
+        // capture took a while to deliver the first frame after the start
+        let first_frame = Instant::now() + Duration::from_secs(3);
+        let mut first_frame_at = None;
+
+        assert_eq!(fade_in_level(&mut first_frame_at, first_frame, 500), 0.0);
+        let later = first_frame + Duration::from_millis(250);
+        assert_eq!(fade_in_level(&mut first_frame_at, later, 500), 0.5);
+        let done = first_frame + Duration::from_millis(600);
+        assert_eq!(fade_in_level(&mut first_frame_at, done, 500), 1.0);
+        assert_eq!(first_frame_at, Some(first_frame));
+    }
+
+    #[test]
+    fn rgbw_packet_keeps_every_channel() {
+        let packet = wled_packet(WledType::Rgbw, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(
+            packet,
+            [3, REALTIME_TIMEOUT_SECONDS, 1, 2, 3, 4, 5, 6, 7, 8]
+        );
+    }
+
+    #[test]
+    fn rgb_packet_adds_white_to_other_channels() {
+        let packet = wled_packet(WledType::Rgb, &[1, 2, 3, 4, 250, 6, 7, 8]);
+        assert_eq!(packet, [2, REALTIME_TIMEOUT_SECONDS, 5, 6, 7, 255, 14, 15]);
     }
 }
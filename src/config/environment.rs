@@ -0,0 +1,137 @@
+use std::{env, mem};
+
+use serde::Serialize;
+use toml::{Table, Value};
+
+use super::{error::ValidationIssue, location::CONFIG_PATH_ENV, profile::merge, Config, Profile};
+
+const PREFIX: &str = "WLED_AMBILIGHT_";
+/// Fields outside of `[[pipelines]]` which can be overridden, e.g. `WLED_AMBILIGHT_API__ADDRESS`
+const TOP_LEVEL_FIELDS: [&str; 4] = ["active_profile", "master_brightness", "api", "logging"];
+
+/// Applies `WLED_AMBILIGHT_<FIELD>` variables, top level fields are set in `config`, anything
+/// else is a pipeline field applied to every pipeline. Pipeline fields are kept in
+/// `config.environment` too, so they are applied again after the active profile and win over
+/// it. Nested fields are separated by `__`.
+pub fn apply_overrides(config: &mut Config) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let mut issue = |field: &str, message: String| {
+        issues.push(ValidationIssue {
+            field: field.to_string(),
+            message,
+        })
+    };
+
+    for (name, value) in env::vars() {
+        let Some(field) = name.strip_prefix(PREFIX) else {
+            continue;
+        };
+        if name == CONFIG_PATH_ENV {
+            continue;
+        }
+
+        let path: Vec<String> = field.to_lowercase().split("__").map(String::from).collect();
+        // profile names are free text, `true` or `1` would not be read as a string
+        if path == ["active_profile"] {
+            config.active_profile = Some(value);
+            continue;
+        }
+
+        let overrides = nested_table(&path, parse_value(&value));
+        if TOP_LEVEL_FIELDS.contains(&path[0].as_str()) {
+            match override_top_level(config, &overrides, &path) {
+                Ok(()) if path[0] == "master_brightness" => {
+                    config.master_brightness_variable = Some(name.clone())
+                }
+                Ok(()) => {}
+                Err(message) => issue(&name, message),
+            }
+            continue;
+        }
+
+        let profile = Profile {
+            name: name.clone(),
+            overrides,
+        };
+        let mut overridden = Vec::with_capacity(config.pipelines.len());
+        for pipeline in &config.pipelines {
+            match profile.apply(pipeline) {
+                Ok(pipeline) if contains_path(&pipeline, &path) => overridden.push(pipeline),
+                Ok(_) => {
+                    issue(&name, "there is no such field".to_string());
+                    break;
+                }
+                Err(err) => {
+                    issue(&name, err.to_string());
+                    break;
+                }
+            }
+        }
+        if overridden.len() == config.pipelines.len() {
+            config.pipelines = overridden;
+            merge(&mut config.environment.overrides, &profile.overrides);
+        }
+    }
+
+    issues
+}
+
+fn override_top_level(
+    config: &mut Config,
+    overrides: &Table,
+    path: &[String],
+) -> Result<(), String> {
+    let mut value = Value::try_from(&*config).expect("config is always serializable");
+    if let Value::Table(table) = &mut value {
+        merge(table, overrides);
+    }
+    let overridden: Config = value.try_into().map_err(|err| err.to_string())?;
+    if !contains_path(&overridden, path) {
+        return Err("there is no such field".to_string());
+    }
+    // fields which are not serialized are kept
+    *config = Config {
+        dir: mem::take(&mut config.dir),
+        environment: mem::take(&mut config.environment),
+        master_brightness_variable: config.master_brightness_variable.take(),
+        ..overridden
+    };
+    Ok(())
+}
+
+/// Values are read as TOML, anything which is not valid TOML is taken as a string
+fn parse_value(value: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+fn nested_table(path: &[String], value: Value) -> Table {
+    let mut table = Table::new();
+    match path {
+        [key] => {
+            table.insert(key.clone(), value);
+        }
+        [key, rest @ ..] => {
+            table.insert(key.clone(), Value::Table(nested_table(rest, value)));
+        }
+        [] => {}
+    }
+    table
+}
+
+/// Serde silently skips unknown fields, they are missing when `config` is serialized again
+fn contains_path(config: &impl Serialize, path: &[String]) -> bool {
+    let mut value = Value::try_from(config).expect("config is always serializable");
+    for key in path {
+        match value {
+            Value::Table(mut table) => match table.remove(key) {
+                Some(next) => value = next,
+                None => return false,
+            },
+            _ => return false,
+        }
+    }
+    true
+}
@@ -0,0 +1,65 @@
+use std::{fmt, io, path::PathBuf};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    Invalid {
+        path: PathBuf,
+        issues: Vec<ValidationIssue>,
+    },
+    /// File could not be parsed to change a value in it, it was left as it is
+    Edit {
+        path: PathBuf,
+        error: toml_edit::TomlError,
+    },
+}
+
+/// Single problem found in the config, `field` is e.g. `pipelines[0].max_fps`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationIssue {
+    pub field: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, error } => {
+                write!(f, "Could not access {}: {}", path.display(), error)
+            }
+            ConfigError::Parse { path, error } => {
+                write!(f, "Could not parse {}:\n{}", path.display(), error)
+            }
+            ConfigError::Invalid { path, issues } => {
+                write!(f, "Invalid settings in {}:", path.display())?;
+                for issue in issues {
+                    write!(f, "\n  {}", issue)?;
+                }
+                Ok(())
+            }
+            ConfigError::Edit { path, error } => {
+                write!(
+                    f,
+                    "Could not parse {} to update it:\n{}",
+                    path.display(),
+                    error
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
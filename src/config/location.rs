@@ -0,0 +1,49 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+pub const CONFIG_PATH_ENV: &str = "WLED_AMBILIGHT_CONFIG";
+const FILE_NAME: &str = "config.toml";
+
+/// Config file to use: explicit path, `WLED_AMBILIGHT_CONFIG`, existing file in user config
+/// directory, existing file next to the executable. When there is none, a new one belongs
+/// into user config directory.
+pub fn locate(explicit: Option<&Path>) -> PathBuf {
+    if let Some(path) = explicit {
+        return path.to_path_buf();
+    }
+    if let Some(path) = env::var_os(CONFIG_PATH_ENV) {
+        return PathBuf::from(path);
+    }
+
+    let user_config = user_config_dir().map(|dir| dir.join(FILE_NAME));
+    let next_to_exe = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(FILE_NAME)));
+
+    if let Some(existing) = [&user_config, &next_to_exe]
+        .into_iter()
+        .flatten()
+        .find(|path| path.exists())
+    {
+        return existing.clone();
+    }
+
+    user_config
+        .or(next_to_exe)
+        .unwrap_or_else(|| PathBuf::from(FILE_NAME))
+}
+
+#[cfg(windows)]
+fn user_config_dir() -> Option<PathBuf> {
+    env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join("WLED Ambilight"))
+}
+
+#[cfg(not(windows))]
+fn user_config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("wled-ambilight"))
+}
@@ -0,0 +1,189 @@
+use std::path::Path;
+
+use toml::{value::Table, Value};
+
+use super::{error::ValidationIssue, with_readme, ConfigError, CONFIG_VERSION};
+
+/// Fields of a single display config before pipelines were introduced
+const VERSION_1_PIPELINE_FIELDS: [&str; 9] = [
+    "display_index",
+    "gpu_index",
+    "led_horizontal_count",
+    "led_vertical_count",
+    "include_cursor",
+    "max_fps",
+    "enable_v_sync",
+    "wled_type",
+    "wled_ip",
+];
+
+/// Upgrades config of an older version, returns version of `content` and the upgraded file
+/// content or `None` when `content` is already of the current version
+pub fn migrate(path: &Path, content: &str) -> Result<Option<(u32, String)>, ConfigError> {
+    let mut raw: Value = toml::from_str(content).map_err(|error| ConfigError::Parse {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    let invalid = |message: String| ConfigError::Invalid {
+        path: path.to_path_buf(),
+        issues: vec![ValidationIssue {
+            field: "version".to_string(),
+            message,
+        }],
+    };
+
+    let Some(table) = raw.as_table_mut() else {
+        return Ok(None);
+    };
+    let original_version = detect_version(table).map_err(invalid)?;
+    if original_version == CONFIG_VERSION {
+        return Ok(None);
+    }
+    if original_version > CONFIG_VERSION {
+        return Err(invalid(format!(
+            "file was written by newer version of the app ({}), this one supports up to {}",
+            original_version, CONFIG_VERSION
+        )));
+    }
+
+    let mut version = original_version;
+    while version < CONFIG_VERSION {
+        match version {
+            1 => version_1_to_2(table),
+            2 => version_2_to_3(table),
+            _ => unreachable!("missing migration from version {}", version),
+        }
+        version += 1;
+    }
+    table.insert("version".to_string(), Value::Integer(CONFIG_VERSION as i64));
+
+    let content = toml::to_string(&raw).expect("parsed config is always serializable");
+    Ok(Some((original_version, with_readme(&content))))
+}
+
+/// Files before version 3 have no version field
+fn detect_version(table: &Table) -> Result<u32, String> {
+    match table.get("version") {
+        Some(Value::Integer(version)) if *version > 0 => Ok(*version as u32),
+        Some(value) => Err(format!("has to be a positive number, found {}", value)),
+        None if table.contains_key("pipelines") => Ok(2),
+        None => Ok(1),
+    }
+}
+
+/// Single display settings moved into the first `[[pipelines]]` entry
+fn version_1_to_2(table: &mut Table) {
+    let mut pipeline = Table::new();
+    pipeline.insert(
+        "name".to_string(),
+        Value::String("Main display".to_string()),
+    );
+    for field in VERSION_1_PIPELINE_FIELDS {
+        if let Some(value) = table.remove(field) {
+            pipeline.insert(field.to_string(), value);
+        }
+    }
+    table.insert(
+        "pipelines".to_string(),
+        Value::Array(vec![Value::Table(pipeline)]),
+    );
+}
+
+/// Readme is written as a comment instead of a field
+fn version_2_to_3(table: &mut Table) {
+    table.remove("readme");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, WledType};
+
+    const VERSION_1: &str = r#"
+readme = "old readme"
+display_index = 1
+gpu_index = 0
+led_horizontal_count = 40
+led_vertical_count = 20
+include_cursor = true
+max_fps = 30
+enable_v_sync = false
+wled_type = "Rgb"
+wled_ip = "192.168.1.50"
+"#;
+
+    const VERSION_2: &str = r#"
+readme = "old readme"
+
+[[pipelines]]
+name = "Left"
+led_horizontal_count = 30
+led_vertical_count = 10
+wled_ip = "192.168.1.51"
+"#;
+
+    fn migrated(content: &str) -> (u32, Table) {
+        let (version, content) = migrate(Path::new("config.toml"), content)
+            .unwrap()
+            .expect("older versions are migrated");
+        assert!(content.starts_with("# "), "readme is a comment");
+        (version, toml::from_str(&content).unwrap())
+    }
+
+    #[test]
+    fn version_1_goes_through_every_migration() {
+        let (version, table) = migrated(VERSION_1);
+        assert_eq!(version, 1);
+        assert_eq!(table["version"].as_integer(), Some(CONFIG_VERSION as i64));
+        assert!(!table.contains_key("readme"));
+        assert!(!table.contains_key("wled_ip"));
+
+        let pipelines = table["pipelines"].as_array().unwrap();
+        assert_eq!(pipelines.len(), 1);
+        let pipeline = pipelines[0].as_table().unwrap();
+        assert_eq!(pipeline["name"].as_str(), Some("Main display"));
+        assert_eq!(pipeline.len(), VERSION_1_PIPELINE_FIELDS.len() + 1);
+        assert_eq!(pipeline["led_horizontal_count"].as_integer(), Some(40));
+        assert_eq!(pipeline["wled_ip"].as_str(), Some("192.168.1.50"));
+    }
+
+    #[test]
+    fn version_2_only_loses_readme() {
+        let (version, table) = migrated(VERSION_2);
+        assert_eq!(version, 2);
+        assert_eq!(table["version"].as_integer(), Some(CONFIG_VERSION as i64));
+        assert!(!table.contains_key("readme"));
+        let pipeline = table["pipelines"][0].as_table().unwrap();
+        assert_eq!(pipeline["name"].as_str(), Some("Left"));
+        assert_eq!(pipeline["led_vertical_count"].as_integer(), Some(10));
+    }
+
+    #[test]
+    fn migrated_version_1_loads() {
+        let config = Config::parse(Path::new("config.toml"), VERSION_1).unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        let pipeline = &config.pipelines[0];
+        assert_eq!(pipeline.display_index, 1);
+        assert_eq!(pipeline.max_fps, 30);
+        assert!(!pipeline.enable_v_sync);
+        assert_eq!(pipeline.wled_type, WledType::Rgb);
+    }
+
+    #[test]
+    fn current_version_is_left_alone() {
+        let content = format!("version = {}\n", CONFIG_VERSION);
+        assert!(migrate(Path::new("config.toml"), &content)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn newer_and_broken_versions_are_rejected() {
+        for content in ["version = 99\n", "version = 0\n", "version = \"3\"\n"] {
+            match migrate(Path::new("config.toml"), content) {
+                Err(ConfigError::Invalid { issues, .. }) => assert_eq!(issues[0].field, "version"),
+                result => panic!("{} migrated to {:?}", content, result),
+            }
+        }
+    }
+}
@@ -0,0 +1,713 @@
+mod environment;
+mod error;
+mod location;
+mod migration;
+mod profile;
+mod validation;
+mod watcher;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub use self::error::ConfigError;
+pub use self::location::locate;
+pub use self::profile::Profile;
+pub use self::validation::{MASTER_BRIGHTNESS_RANGE, MULTIPLIER_RANGE};
+pub use self::watcher::ConfigWatcher;
+
+/// Bump together with a new step in `migration` whenever fields are renamed, moved or removed
+pub const CONFIG_VERSION: u32 = 3;
+
+/// Written as comment on top of the config file
+const README: &str = r#"Each [[pipelines]] entry captures one display and drives one WLED device.
+Pipelines run independently, add another entry for each monitor with its own controller.
+Total LED count = Horizontal + Vertical LEDs.
+For best experience try matching aspect ratio of your display: H/(V+2) ≈ 16/9.
+┌──────────────────────────────────────┐
+│B →         Horizontal LEDs         ↓ │
+├───┬──────────────────────────────┬───┤
+│ E │                              │   │
+│   │                              │ V │
+│ V │                              │ e │
+│ e │                              │ r │
+│ r │                              │ t │
+│ t │     Display (front screen)   │ i │
+│ i │                              │ c │
+│ c │                              │ a │
+│ a │                              │ l │
+│ l │                              │   │
+├───┴──────────────────────────────┴───┤
+│ ↑          Horizontal LEDs         ← │
+└──────────────────────────────────────┘
+With enabled V-Sync max_fps is ignored.
+B is starting point (index 0), clock-wise indexing, until E (last index).
+Missing fields get their default values.
+master_brightness:
+  dims every pipeline (0.0-1.0) after all other processing, in linear light so hues stay,
+  changed from the tray, POST /master_brightness or --master-brightness and saved here,
+  while WLED_AMBILIGHT_MASTER_BRIGHTNESS is set those changes are rejected
+profiles:
+  [profiles.overrides] holds pipeline fields which replace values of every pipeline
+  while the profile is selected in the tray, active_profile is selected on start
+reducer:
+  "Mean" averages the screen area behind every LED (fastest)
+  "Median", "Mode" or "Dominant" keep distinct colors from mixing,
+  "Dominant" uses dominant_clusters (2-3) k-means clusters and prefers saturated colors
+wled_type:
+  "Rgbw" sends RGBW values to WLED (at most 367 LEDs)
+  "Rgb" sends RGB values to WLED with white added to them (at most 490 LEDs)
+wled_port:
+  UDP port of WLED realtime protocol (default 21324), see Sync Interfaces in WLED
+change_tolerance:
+  frames whose every channel differs at most this much (0-255) from the last sent frame
+  are skipped, unchanged colors are still sent every second so WLED stays in realtime mode
+output_fps:
+  colors are sent at this fixed rate, captured frames (max_fps or display refresh rate
+  with V-Sync) only update what is sent next. interpolate blends between the last two
+  captured frames, which is smoother when output_fps is above capture rate but one frame later
+fade_in_ms, fade_out_ms:
+  colors fade in from black after start and fade out to fade_out_color ([r, g, b]) on stop,
+  0 switches right away
+adjustments:
+  saturation, brightness and contrast are multipliers where 1.0 keeps colors untouched
+  saturation_mode is "Hsv" or "Oklab" (keeps perceived lightness)
+  min_brightness (0-255) keeps the room from going pitch black
+dark_scene (optional):
+  same fields as adjustments, used while average luma is below luma_threshold (0.0-1.0)
+lut (optional):
+  path to .cube 3D LUT file (relative to this config), interpolation is "Trilinear" or
+  "Tetrahedral", edits of the file apply while running like edits of this config
+power_limiter (optional):
+  estimates current of every frame as sent (white of an Rgb strip lights every channel)
+  from milliamps_per_channel (fully lit channel),
+  idle_milliamps_per_led and led_count (defaults to LEDs in layout),
+  frames above psu_budget_milliamps are dimmed (WLED's ABL does not work in realtime mode)
+standby (optional):
+  streaming stops once the screen is black (every channel up to black_threshold) or
+  unchanged (within tolerance) for after_seconds, WLED then switches to preset (1-250)
+  or goes back to its own effect after a few seconds, streaming resumes on the next change
+fallback (optional):
+  effect shown once no frame was captured for after_ms (UAC prompt, exclusive fullscreen),
+  "Rainbow", "Breathing" or "Candle" of color ([r, g, b]), or "HoldLast" blurred last
+  frame, period_seconds is the length of one rainbow or breathing cycle, the effect fades
+  in and back out to captured colors over fade_ms, the power limiter dims effects too
+api (optional):
+  [api] enables HTTP control API on address (default "127.0.0.1:8585"),
+  GET /status, POST /start, /stop with optional {"pipeline": name},
+  POST /profile with {"profile": name or null},
+  POST /brightness with {"brightness": 0.0-4.0 or null} replaces adjustments.brightness of
+  every pipeline until restart, null goes back to the config, it is one of the color
+  adjustments and can boost, POST /master_brightness with {"master_brightness": 0.0-1.0} dims the final
+  output and is saved here, see master_brightness
+  the same server has live LED preview on http://<address>/preview and Prometheus metrics
+  on /metrics, without [api] neither is served
+logging:
+  level is "Error", "Warn", "Info", "Debug" or "Trace"
+  file (optional) is relative to this config, without it the log goes to the console,
+  file is rotated when it grows over max_file_size_kb, max_files older files are kept
+"#;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WledType {
+    Rgb = 2,
+    Rgbw = 3,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Config {
+    /// Version of the file layout, older files are migrated when loaded
+    pub version: u32,
+    /// Name of the profile selected on start, no profile keeps pipelines as they are
+    pub active_profile: Option<String>,
+    /// Share of light output of every pipeline, set from the tray, API and command line
+    pub master_brightness: f64,
+    /// Every pipeline captures one display and streams it to one WLED device
+    pub pipelines: Vec<PipelineConfig>,
+    pub profiles: Vec<Profile>,
+    /// HTTP control API, disabled when missing
+    #[serde(default)]
+    pub api: Option<ApiConfig>,
+    pub logging: LoggingConfig,
+    /// Directory of the config file, relative paths in the config are resolved against it
+    #[serde(skip)]
+    dir: PathBuf,
+    /// Pipeline fields set by environment variables, applied after the active profile
+    #[serde(skip)]
+    environment: Profile,
+    /// Variable overriding `master_brightness`, it would win again on the next reload
+    #[serde(skip)]
+    master_brightness_variable: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct PipelineConfig {
+    /// Name shown in the tray menu
+    pub name: String,
+    pub display_index: u32,
+    pub gpu_index: u32,
+    /// Horizontal count of LEDs including border pixels
+    /// Try to match aspect ratio of your display. E.g. H/(V+2) ≈ 16/9
+    /**
+       ┌──────────────────────────────────────┐
+       │            Horizontal LEDs           │
+       ├───┬──────────────────────────────┬───┤
+       │ V │                              │ V │
+       │ e │                              │ e │
+       │ r │                              │ r │
+       │ t │           Display            │ t │
+       │ i │                              │ i │
+       │ c │                              │ c │
+       │ a │                              │ a │
+       │ l │                              │ l │
+       ├───┴──────────────────────────────┴───┤
+       │            Horizontal LEDs           │
+       └──────────────────────────────────────┘
+    */
+    pub led_horizontal_count: u32,
+    /// Veritcal count of LEDs NOT including border pixels - total LED count is (horizontal + vertical) * 2
+    /**
+       ┌──────────────────────────────────────┐
+       │            Horizontal LEDs           │
+       ├───┬──────────────────────────────┬───┤
+       │ V │                              │ V │
+       │ e │                              │ e │
+       │ r │                              │ r │
+       │ t │           Display            │ t │
+       │ i │                              │ i │
+       │ c │                              │ c │
+       │ a │                              │ a │
+       │ l │                              │ l │
+       ├───┴──────────────────────────────┴───┤
+       │            Horizontal LEDs           │
+       └──────────────────────────────────────┘
+    */
+    pub led_vertical_count: u32,
+    pub include_cursor: bool,
+    /// How colors of the screen area behind one LED are reduced into a single color
+    pub reducer: ColorReducer,
+    /// Number of k-means clusters (2-3) used by `ColorReducer::Dominant`
+    pub dominant_clusters: u32,
+    pub max_fps: u32,
+    pub enable_v_sync: bool,
+    pub wled_type: WledType,
+    pub wled_ip: String,
+    /// UDP port of WLED realtime protocol, WLED sets it in Sync Interfaces
+    pub wled_port: u16,
+    /// Frames whose every channel differs at most this much from the last sent frame are not
+    /// sent, 0 skips only identical frames
+    pub change_tolerance: u8,
+    /// Colors are sent at this fixed rate no matter how fast frames are captured
+    pub output_fps: u32,
+    /// Blend between the last two captured frames instead of repeating the latest
+    pub interpolate: bool,
+    /// Brightness ramps up from black over this long after the pipeline starts
+    pub fade_in_ms: u32,
+    /// Last frame fades into `fade_out_color` over this long when the pipeline is stopped
+    pub fade_out_ms: u32,
+    /// RGB color the strip fades to before WLED gets it back
+    pub fade_out_color: [u8; 3],
+    /// Copied from `Config::master_brightness`, applied after everything else
+    #[serde(skip, default = "full_brightness")]
+    pub master_brightness: f64,
+    /// Color adjustments applied to the extracted colors before they are sent
+    pub adjustments: ColorAdjustments,
+    /// Alternative adjustments used while the picture is dark
+    pub dark_scene: Option<DarkSceneConfig>,
+    /// 3D LUT applied after the adjustments
+    pub lut: Option<LutConfig>,
+    /// Dims frames which would draw more current than the PSU can deliver
+    pub power_limiter: Option<PowerLimiterConfig>,
+    /// Stops streaming while the screen stays black or unchanged
+    pub standby: Option<StandbyConfig>,
+    /// Effect shown while no frames are captured
+    pub fallback: Option<FallbackConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorReducer {
+    /// Bilinear average, fastest but mixes distinct colors (half red, half blue gives purple)
+    #[default]
+    Mean,
+    /// Median of every channel
+    Median,
+    /// Most frequent color of a quantized histogram
+    Mode,
+    /// Heaviest k-means cluster, saturated pixels weigh more
+    Dominant,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaturationMode {
+    /// Scales saturation in HSV space, cheap but shifts perceived brightness
+    Hsv,
+    /// Scales chroma in Oklab space, keeps perceived lightness
+    Oklab,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ColorAdjustments {
+    /// 1.0 keeps colors untouched, values above 1.0 make them more vivid
+    pub saturation: f64,
+    pub saturation_mode: SaturationMode,
+    /// Multiplier of all channels, 1.0 keeps colors untouched
+    pub brightness: f64,
+    /// Contrast around middle gray, 1.0 keeps colors untouched
+    pub contrast: f64,
+    /// No LED gets darker than this value (0-255)
+    pub min_brightness: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LutInterpolation {
+    Trilinear,
+    /// Slightly slower, keeps neutral colors neutral
+    Tetrahedral,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LutConfig {
+    /// Path to Adobe/Resolve `.cube` file (usually size 17, 33 or 65), relative to the config
+    pub path: String,
+    pub interpolation: LutInterpolation,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PowerLimiterConfig {
+    /// Current of one fully lit color channel, WS2812B draws around 12-20 mA
+    pub milliamps_per_channel: f64,
+    /// Current of one LED with all channels off
+    #[serde(default)]
+    pub idle_milliamps_per_led: f64,
+    /// Current the PSU can deliver to the strip
+    pub psu_budget_milliamps: f64,
+    /// Number of LEDs on the strip, defaults to number of LEDs in the layout
+    #[serde(default)]
+    pub led_count: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct StandbyConfig {
+    /// How long the screen has to stay black or unchanged
+    pub after_seconds: u32,
+    /// Extracted colors whose every channel is at most this are black
+    pub black_threshold: u8,
+    /// Frames whose every channel differs at most this much from the picture standby is
+    /// waiting on count as unchanged
+    pub tolerance: u8,
+    /// WLED preset switched to on standby, without it WLED goes back to its own effect after
+    /// the realtime timeout
+    pub preset: Option<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FallbackEffect {
+    /// Hues slowly moving along the strip
+    Rainbow,
+    /// `color` slowly fading in and out
+    Breathing,
+    /// `color` flickering on every LED
+    Candle,
+    /// Last captured colors blurred along the strip
+    HoldLast,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct FallbackConfig {
+    /// How long no frame has to be captured before the effect takes over
+    pub after_ms: u32,
+    pub effect: FallbackEffect,
+    /// RGB color of `Breathing` and `Candle`
+    pub color: [u8; 3],
+    /// Length of one cycle of `Rainbow` and `Breathing`
+    pub period_seconds: f64,
+    /// How long the effect fades in and back out to captured colors
+    pub fade_ms: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DarkSceneConfig {
+    /// Average luma (0.0-1.0) of all LEDs below which the scene counts as dark
+    pub luma_threshold: f64,
+    #[serde(flatten)]
+    pub adjustments: ColorAdjustments,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ApiConfig {
+    /// Address and port the server listens on, anyone who can reach it controls the lights
+    pub address: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub level: LogLevel,
+    /// Log file relative to the config file, `--log-file` takes precedence
+    #[serde(default)]
+    pub file: Option<String>,
+    pub max_file_size_kb: u64,
+    /// Number of rotated files kept besides the current one
+    pub max_files: u32,
+}
+
+impl Config {
+    /// Loads config from `path`, creates default one when there is none yet.
+    /// Files of older versions are upgraded in place, original is kept as a backup.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let io_error = |error| ConfigError::Io {
+            path: path.to_path_buf(),
+            error,
+        };
+
+        if !path.exists() {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).map_err(io_error)?;
+            }
+            fs::write(path, Self::default().to_file_content()).map_err(io_error)?;
+            info!("Created default config {}", path.display());
+        };
+
+        let content = fs::read_to_string(path).map_err(io_error)?;
+        if let Some((version, migrated)) = migration::migrate(path, &content)? {
+            let backup = path.with_extension(format!("toml.v{}.bak", version));
+            fs::copy(path, &backup).map_err(io_error)?;
+            fs::write(path, &migrated).map_err(io_error)?;
+            info!(
+                "Config upgraded from version {} to {}, original saved to {}",
+                version,
+                CONFIG_VERSION,
+                backup.display()
+            );
+            return Self::parse(path, &migrated);
+        }
+
+        Self::parse(path, &content)
+    }
+
+    /// Parses and validates content of config file at `path`, older versions are migrated
+    /// in memory only. `WLED_AMBILIGHT_<FIELD>` environment variables override the content.
+    pub fn parse(path: &Path, content: &str) -> Result<Self, ConfigError> {
+        if let Some((_, migrated)) = migration::migrate(path, content)? {
+            return Self::parse(path, &migrated);
+        }
+
+        let parse_error = |error| ConfigError::Parse {
+            path: path.to_path_buf(),
+            error,
+        };
+        let raw: toml::Value = toml::from_str(content).map_err(parse_error)?;
+        let mut config: Self = toml::from_str(content).map_err(parse_error)?;
+        config.dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let mut issues = environment::apply_overrides(&mut config);
+
+        issues.extend(validation::validate(&config, &raw));
+        if !issues.is_empty() {
+            return Err(ConfigError::Invalid {
+                path: path.to_path_buf(),
+                issues,
+            });
+        }
+
+        Ok(config)
+    }
+
+    /// Pipelines with overrides of `profile` and then environment variables applied and paths
+    /// resolved against the config directory
+    pub fn pipelines_with_profile(
+        &self,
+        profile: Option<&str>,
+    ) -> Result<Vec<PipelineConfig>, String> {
+        let mut pipelines = match profile {
+            None => self.pipelines.clone(),
+            Some(name) => {
+                let profile = self
+                    .profiles
+                    .iter()
+                    .find(|p| p.name == name)
+                    .ok_or_else(|| format!("there is no profile `{}`", name))?;
+
+                self.pipelines
+                    .iter()
+                    .map(|pipeline| {
+                        profile.apply(pipeline).map_err(|err| {
+                            format!(
+                                "profile `{}` can not be applied to `{}`: {}",
+                                profile.name, pipeline.name, err
+                            )
+                        })
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+        };
+
+        for pipeline in &mut pipelines {
+            if !self.environment.overrides.is_empty() {
+                *pipeline = self.environment.apply(pipeline).map_err(|err| {
+                    format!(
+                        "environment variables can not be applied to `{}`: {}",
+                        pipeline.name, err
+                    )
+                })?;
+            }
+            if let Some(lut) = &mut pipeline.lut {
+                lut.path = self.resolve(&lut.path).to_string_lossy().to_string();
+            }
+        }
+        Ok(pipelines)
+    }
+
+    /// `path` written in the config, relative to the config file unless it is absolute
+    fn resolve(&self, path: &str) -> PathBuf {
+        self.dir.join(path)
+    }
+
+    /// Environment variable overriding `master_brightness`, while it is set changes from the
+    /// tray, API or command line would be undone by the next reload
+    pub fn master_brightness_variable(&self) -> Option<&str> {
+        self.master_brightness_variable.as_deref()
+    }
+
+    /// Writes `master_brightness` into the config file at `path`, the rest of the file
+    /// including comments stays as it is
+    pub fn save_master_brightness(path: &Path, master_brightness: f64) -> Result<(), ConfigError> {
+        let io_error = |error| ConfigError::Io {
+            path: path.to_path_buf(),
+            error,
+        };
+        let edit_error = |error| ConfigError::Edit {
+            path: path.to_path_buf(),
+            error,
+        };
+
+        let content = fs::read_to_string(path).map_err(io_error)?;
+        let mut document = content.parse::<toml_edit::Document>().map_err(edit_error)?;
+        // rounded, so repeated steps don't leave 0.30000000000000004 in the file
+        let rounded = (master_brightness * 100.0).round() / 100.0;
+        document["master_brightness"] = toml_edit::value(rounded);
+        fs::write(path, document.to_string()).map_err(io_error)
+    }
+
+    /// Serialized config with readme on top
+    pub fn to_file_content(&self) -> String {
+        with_readme(&toml::to_string(self).expect("config is always serializable"))
+    }
+}
+
+fn with_readme(content: &str) -> String {
+    let mut file_content: String = README
+        .lines()
+        .map(|line| format!("# {}\n", line).replace("# \n", "#\n"))
+        .collect();
+    file_content.push('\n');
+    file_content.push_str(content);
+    file_content
+}
+
+fn full_brightness() -> f64 {
+    1.0
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            version: CONFIG_VERSION,
+            active_profile: None,
+            master_brightness: 1.0,
+            pipelines: vec![PipelineConfig::default()],
+            profiles: vec![
+                // dominant colors of a dark room, dark scenes stay dim instead of gray
+                Profile {
+                    name: "Movies".to_string(),
+                    overrides: toml::toml! {
+                        reducer = "Dominant"
+                        fade_in_ms = 1500
+                        fade_out_ms = 1500
+                        [adjustments]
+                        saturation = 1.3
+                        saturation_mode = "Oklab"
+                        contrast = 1.1
+                        [dark_scene]
+                        luma_threshold = 0.08
+                        saturation = 1.0
+                        brightness = 0.6
+                    },
+                },
+                // lowest latency, the last frame stays while exclusive fullscreen blocks capture
+                Profile {
+                    name: "Gaming".to_string(),
+                    overrides: toml::toml! {
+                        reducer = "Mean"
+                        enable_v_sync = false
+                        max_fps = 144
+                        output_fps = 120
+                        fade_in_ms = 0
+                        fade_out_ms = 0
+                        [fallback]
+                        after_ms = 500
+                        effect = "HoldLast"
+                    },
+                },
+                // calm light for work, streaming stops while the screen sits unchanged
+                Profile {
+                    name: "Desktop".to_string(),
+                    overrides: toml::toml! {
+                        max_fps = 30
+                        output_fps = 30
+                        change_tolerance = 2
+                        [adjustments]
+                        brightness = 0.5
+                        saturation = 0.8
+                        [standby]
+                        after_seconds = 300
+                    },
+                },
+            ],
+            api: None,
+            logging: LoggingConfig::default(),
+            dir: PathBuf::new(),
+            environment: Profile::default(),
+            master_brightness_variable: None,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: LogLevel::Info,
+            file: None,
+            max_file_size_kb: 1024,
+            max_files: 3,
+        }
+    }
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        ApiConfig {
+            address: "127.0.0.1:8585".to_string(),
+        }
+    }
+}
+
+impl PipelineConfig {
+    /// Whether switching from `self` to `other` needs new capture, settings which only affect
+    /// processing and sending of colors can be changed on a running pipeline
+    pub fn needs_restart(&self, other: &Self) -> bool {
+        self.display_index != other.display_index
+            || self.gpu_index != other.gpu_index
+            || self.led_horizontal_count != other.led_horizontal_count
+            || self.led_vertical_count != other.led_vertical_count
+            || self.include_cursor != other.include_cursor
+            || self.reducer != other.reducer
+            || self.dominant_clusters != other.dominant_clusters
+            || self.max_fps != other.max_fps
+            || self.enable_v_sync != other.enable_v_sync
+    }
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            name: "Main display".to_string(),
+            display_index: 0,
+            gpu_index: 0,
+            include_cursor: true,
+            reducer: ColorReducer::Mean,
+            dominant_clusters: 3,
+            led_horizontal_count: 27,
+            led_vertical_count: 14,
+            max_fps: 60,
+            enable_v_sync: true,
+            wled_type: WledType::Rgbw,
+            wled_ip: "192.168.0.150".to_string(),
+            wled_port: 21324,
+            change_tolerance: 0,
+            output_fps: 60,
+            interpolate: false,
+            fade_in_ms: 500,
+            fade_out_ms: 500,
+            fade_out_color: [0, 0, 0],
+            master_brightness: full_brightness(),
+            adjustments: ColorAdjustments::default(),
+            dark_scene: None,
+            lut: None,
+            power_limiter: None,
+            standby: None,
+            fallback: None,
+        }
+    }
+}
+
+impl Default for FallbackConfig {
+    fn default() -> Self {
+        FallbackConfig {
+            after_ms: 1000,
+            effect: FallbackEffect::HoldLast,
+            color: [255, 120, 20],
+            period_seconds: 10.0,
+            fade_ms: 500,
+        }
+    }
+}
+
+impl Default for StandbyConfig {
+    fn default() -> Self {
+        StandbyConfig {
+            after_seconds: 300,
+            black_threshold: 8,
+            tolerance: 2,
+            preset: None,
+        }
+    }
+}
+
+impl Default for ColorAdjustments {
+    fn default() -> Self {
+        ColorAdjustments {
+            saturation: 1.0,
+            saturation_mode: SaturationMode::Oklab,
+            brightness: 1.0,
+            contrast: 1.0,
+            min_brightness: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_match_new_file() {
+        let path = Path::new("config.toml");
+        let created = Config::parse(path, &Config::default().to_file_content()).unwrap();
+        let partial = Config::parse(
+            path,
+            "version = 3\n[[pipelines]]\nname = \"Main display\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(created.pipelines, Config::default().pipelines);
+        assert_eq!(partial.pipelines, created.pipelines);
+    }
+}
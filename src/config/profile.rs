@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+use super::PipelineConfig;
+
+/// Named set of overrides applied to every pipeline, switchable from the tray
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct Profile {
+    pub name: String,
+    /// Fields overriding every pipeline, same names and nesting as in `[[pipelines]]`
+    pub overrides: Table,
+}
+
+impl Profile {
+    pub fn apply(&self, pipeline: &PipelineConfig) -> Result<PipelineConfig, toml::de::Error> {
+        let mut value = Value::try_from(pipeline).expect("pipeline is always serializable");
+        if let Value::Table(table) = &mut value {
+            merge(table, &self.overrides);
+        }
+        value.try_into()
+    }
+}
+
+/// Nested tables are merged, everything else in `overrides` replaces value in `table`
+pub(super) fn merge(table: &mut Table, overrides: &Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(key), value) {
+            (Some(Value::Table(table)), Value::Table(overrides)) => merge(table, overrides),
+            _ => {
+                table.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,502 @@
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+use crate::processing::Lut3d;
+
+use super::{error::ValidationIssue, Config, PipelineConfig, WledType};
+
+const LED_COUNT_RANGE: RangeInclusive<u32> = 2..=1000;
+const MAX_FPS_RANGE: RangeInclusive<u32> = 1..=500;
+const DOMINANT_CLUSTERS_RANGE: RangeInclusive<u32> = 2..=3;
+pub const MULTIPLIER_RANGE: RangeInclusive<f64> = 0.0..=4.0;
+const LUMA_RANGE: RangeInclusive<f64> = 0.0..=1.0;
+pub const MASTER_BRIGHTNESS_RANGE: RangeInclusive<f64> = 0.0..=1.0;
+/// WLED stores presets 1-250
+const PRESET_RANGE: RangeInclusive<u32> = 1..=250;
+
+/// Checks values which deserialize fine but can't work, `raw` is the file as it was parsed
+/// and is used to find misspelled fields which serde would silently ignore
+pub fn validate(config: &Config, raw: &toml::Value) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+
+    if let Ok(known) = toml::Value::try_from(config) {
+        find_unknown_fields(raw, &known, "", &mut issues);
+    }
+
+    if config.pipelines.is_empty() {
+        issue(
+            &mut issues,
+            "pipelines",
+            "at least one pipeline is required",
+        );
+    }
+
+    let mut names = HashSet::new();
+    for (index, pipeline) in config.pipelines.iter().enumerate() {
+        let field = |name: &str| format!("pipelines[{}].{}", index, name);
+
+        if pipeline.name.trim().is_empty() {
+            issue(&mut issues, field("name"), "can not be empty");
+        } else if !names.insert(pipeline.name.as_str()) {
+            issue(
+                &mut issues,
+                field("name"),
+                format!("`{}` is used by another pipeline", pipeline.name),
+            );
+        }
+
+        validate_pipeline(pipeline, &config.dir, &field, &mut issues);
+    }
+
+    validate_profiles(config, &mut issues);
+
+    in_float_range(
+        &mut issues,
+        "master_brightness".to_string(),
+        config.master_brightness,
+        MASTER_BRIGHTNESS_RANGE,
+    );
+
+    if config.logging.max_file_size_kb == 0 {
+        issue(&mut issues, "logging.max_file_size_kb", "has to be above 0");
+    }
+
+    if let Some(api) = &config.api {
+        if api.address.parse::<SocketAddr>().is_err() {
+            issue(
+                &mut issues,
+                "api.address",
+                format!(
+                    "`{}` is not an address with port, e.g. 127.0.0.1:8585",
+                    api.address
+                ),
+            );
+        }
+    }
+
+    issues
+}
+
+fn validate_profiles(config: &Config, issues: &mut Vec<ValidationIssue>) {
+    if let Some(active_profile) = &config.active_profile {
+        if !config.profiles.iter().any(|p| &p.name == active_profile) {
+            issue(
+                issues,
+                "active_profile",
+                format!("there is no profile `{}`", active_profile),
+            );
+        }
+    }
+
+    let mut names = HashSet::new();
+    for (index, profile) in config.profiles.iter().enumerate() {
+        let field = |name: &str| format!("profiles[{}].{}", index, name);
+
+        if profile.name.trim().is_empty() {
+            issue(issues, field("name"), "can not be empty");
+        } else if !names.insert(profile.name.as_str()) {
+            issue(
+                issues,
+                field("name"),
+                format!("`{}` is used by another profile", profile.name),
+            );
+        }
+
+        // overrides are checked on every pipeline, only problems of overridden fields are
+        // reported here, the rest is reported for the pipeline itself
+        let mut profile_issues = Vec::new();
+        for pipeline in &config.pipelines {
+            let overridden = match profile.apply(pipeline) {
+                Ok(overridden) => overridden,
+                Err(err) => {
+                    issue(&mut profile_issues, field("overrides"), err.to_string());
+                    continue;
+                }
+            };
+
+            if let Ok(known) = toml::Value::try_from(&overridden) {
+                find_unknown_fields(
+                    &toml::Value::Table(profile.overrides.clone()),
+                    &known,
+                    &field("overrides"),
+                    &mut profile_issues,
+                );
+            }
+
+            let mut pipeline_issues = Vec::new();
+            let overrides_field = |name: &str| field(&format!("overrides.{}", name));
+            validate_pipeline(
+                &overridden,
+                &config.dir,
+                &overrides_field,
+                &mut pipeline_issues,
+            );
+            profile_issues.extend(pipeline_issues.into_iter().filter(|issue| {
+                is_overridden(
+                    &profile.overrides,
+                    &issue.field[overrides_field("").len()..],
+                )
+            }));
+        }
+
+        for profile_issue in profile_issues {
+            if !issues.contains(&profile_issue) {
+                issues.push(profile_issue);
+            }
+        }
+    }
+}
+
+fn validate_pipeline(
+    pipeline: &PipelineConfig,
+    dir: &Path,
+    field: &dyn Fn(&str) -> String,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    in_range(
+        issues,
+        field("led_horizontal_count"),
+        pipeline.led_horizontal_count,
+        LED_COUNT_RANGE,
+    );
+    in_range(
+        issues,
+        field("led_vertical_count"),
+        pipeline.led_vertical_count,
+        LED_COUNT_RANGE,
+    );
+
+    // WLED drops realtime UDP packets larger than this
+    let max_led_count = match pipeline.wled_type {
+        WledType::Rgb => 490,
+        WledType::Rgbw => 367,
+    };
+    // counts too large to add up are out of their range, which is reported above already
+    let led_count = pipeline
+        .led_horizontal_count
+        .checked_add(pipeline.led_vertical_count)
+        .and_then(|count| count.checked_mul(2));
+    if let Some(led_count) = led_count.filter(|&led_count| led_count > max_led_count) {
+        issue(
+            issues,
+            field("wled_type"),
+            format!(
+                "{:?} fits at most {} LEDs into one packet, layout has {}",
+                pipeline.wled_type, max_led_count, led_count
+            ),
+        );
+    }
+
+    in_range(issues, field("max_fps"), pipeline.max_fps, MAX_FPS_RANGE);
+    in_range(
+        issues,
+        field("output_fps"),
+        pipeline.output_fps,
+        MAX_FPS_RANGE,
+    );
+    in_range(
+        issues,
+        field("dominant_clusters"),
+        pipeline.dominant_clusters,
+        DOMINANT_CLUSTERS_RANGE,
+    );
+
+    if !is_valid_host(&pipeline.wled_ip) {
+        issue(
+            issues,
+            field("wled_ip"),
+            format!(
+                "`{}` is neither an IP address nor a host name",
+                pipeline.wled_ip
+            ),
+        );
+    }
+
+    if pipeline.wled_port == 0 {
+        issue(issues, field("wled_port"), "has to be above 0");
+    }
+
+    let adjustments = std::iter::once(("adjustments", &pipeline.adjustments)).chain(
+        pipeline
+            .dark_scene
+            .as_ref()
+            .map(|dark_scene| ("dark_scene", &dark_scene.adjustments)),
+    );
+    for (name, adjustments) in adjustments {
+        for (value_name, value) in [
+            ("saturation", adjustments.saturation),
+            ("brightness", adjustments.brightness),
+            ("contrast", adjustments.contrast),
+        ] {
+            in_float_range(
+                issues,
+                field(&format!("{}.{}", name, value_name)),
+                value,
+                MULTIPLIER_RANGE,
+            );
+        }
+    }
+
+    if let Some(dark_scene) = &pipeline.dark_scene {
+        in_float_range(
+            issues,
+            field("dark_scene.luma_threshold"),
+            dark_scene.luma_threshold,
+            LUMA_RANGE,
+        );
+    }
+
+    if let Some(lut) = &pipeline.lut {
+        if let Err(err) = Lut3d::load_shared(dir.join(&lut.path)) {
+            issue(issues, field("lut.path"), format!("{}: {}", lut.path, err));
+        }
+    }
+
+    if let Some(limiter) = &pipeline.power_limiter {
+        if limiter.milliamps_per_channel.is_nan() || limiter.milliamps_per_channel <= 0.0 {
+            issue(
+                issues,
+                field("power_limiter.milliamps_per_channel"),
+                "has to be above 0",
+            );
+        }
+        if limiter.idle_milliamps_per_led.is_nan() || limiter.idle_milliamps_per_led < 0.0 {
+            issue(
+                issues,
+                field("power_limiter.idle_milliamps_per_led"),
+                "can not be negative",
+            );
+        }
+        let strip_led_count = limiter.led_count.or(led_count).unwrap_or(u32::MAX);
+        if strip_led_count == 0 {
+            issue(
+                issues,
+                field("power_limiter.led_count"),
+                "has to be above 0",
+            );
+        }
+        let idle_milliamps = limiter.idle_milliamps_per_led * strip_led_count as f64;
+        if limiter.psu_budget_milliamps.is_nan() || limiter.psu_budget_milliamps <= idle_milliamps {
+            issue(
+                issues,
+                field("power_limiter.psu_budget_milliamps"),
+                format!(
+                    "has to be above idle current of the strip ({:.0} mA)",
+                    idle_milliamps
+                ),
+            );
+        }
+    }
+
+    if let Some(standby) = &pipeline.standby {
+        if standby.after_seconds == 0 {
+            issue(issues, field("standby.after_seconds"), "has to be above 0");
+        }
+        if let Some(preset) = standby.preset {
+            in_range(issues, field("standby.preset"), preset as u32, PRESET_RANGE);
+        }
+    }
+
+    if let Some(fallback) = &pipeline.fallback {
+        if fallback.period_seconds.is_nan() || fallback.period_seconds <= 0.0 {
+            issue(
+                issues,
+                field("fallback.period_seconds"),
+                "has to be above 0",
+            );
+        }
+    }
+}
+
+/// Whether `field` (e.g. `adjustments.brightness`) or a table containing it is in `overrides`
+fn is_overridden(overrides: &toml::Table, field: &str) -> bool {
+    let mut table = overrides;
+    for key in field.split('.') {
+        match table.get(key) {
+            Some(toml::Value::Table(nested)) => table = nested,
+            Some(_) => return true,
+            None => return false,
+        }
+    }
+    true
+}
+
+fn find_unknown_fields(
+    raw: &toml::Value,
+    known: &toml::Value,
+    path: &str,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    match (raw, known) {
+        (toml::Value::Table(raw), toml::Value::Table(known)) => {
+            for (key, value) in raw {
+                let field = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                match known.get(key) {
+                    Some(known) => find_unknown_fields(value, known, &field, issues),
+                    None => issue(issues, field, "unknown field, check its spelling"),
+                }
+            }
+        }
+        (toml::Value::Array(raw), toml::Value::Array(known)) => {
+            for (index, (raw, known)) in raw.iter().zip(known).enumerate() {
+                find_unknown_fields(raw, known, &format!("{}[{}]", path, index), issues);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn is_valid_host(host: &str) -> bool {
+    if host.parse::<IpAddr>().is_ok() {
+        return true;
+    }
+
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        // all-numeric names are mistyped IP addresses
+        && !host.split('.').all(|label| label.chars().all(|c| c.is_ascii_digit()))
+}
+
+fn in_range(
+    issues: &mut Vec<ValidationIssue>,
+    field: String,
+    value: u32,
+    range: RangeInclusive<u32>,
+) {
+    if !range.contains(&value) {
+        issue(
+            issues,
+            field,
+            format!(
+                "has to be in {}-{}, found {}",
+                range.start(),
+                range.end(),
+                value
+            ),
+        );
+    }
+}
+
+fn in_float_range(
+    issues: &mut Vec<ValidationIssue>,
+    field: String,
+    value: f64,
+    range: RangeInclusive<f64>,
+) {
+    if !range.contains(&value) {
+        issue(
+            issues,
+            field,
+            format!(
+                "has to be in {:.1}-{:.1}, found {}",
+                range.start(),
+                range.end(),
+                value
+            ),
+        );
+    }
+}
+
+fn issue(issues: &mut Vec<ValidationIssue>, field: impl Into<String>, message: impl Into<String>) {
+    issues.push(ValidationIssue {
+        field: field.into(),
+        message: message.into(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(config: &Config, raw: &toml::Value) -> Vec<String> {
+        validate(config, raw)
+            .into_iter()
+            .map(|issue| issue.field)
+            .collect()
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        let config = Config::default();
+        let raw = toml::Value::try_from(&config).unwrap();
+        assert_eq!(fields(&config, &raw), Vec::<String>::new());
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let mut config = Config::default();
+        config.pipelines[0].max_fps = 0;
+        config.pipelines[0].wled_ip = "not a host".to_string();
+        config.pipelines.push(config.pipelines[0].clone());
+        config.pipelines[1].max_fps = 60;
+        config.master_brightness = 2.0;
+        let mut raw = toml::Value::try_from(&config).unwrap();
+        raw["pipelines"][0]
+            .as_table_mut()
+            .unwrap()
+            .insert("max_fsp".to_string(), toml::Value::Integer(60));
+
+        let fields = fields(&config, &raw);
+        for field in [
+            "pipelines[0].max_fsp",
+            "pipelines[0].wled_ip",
+            "pipelines[0].max_fps",
+            "pipelines[1].name",
+            "pipelines[1].wled_ip",
+            "master_brightness",
+        ] {
+            assert!(
+                fields.iter().any(|f| f == field),
+                "{} in {:?}",
+                field,
+                fields
+            );
+        }
+        assert!(!fields.iter().any(|f| f == "pipelines[1].max_fps"));
+    }
+
+    #[test]
+    fn led_count_is_checked_against_wled_type() {
+        let mut config = Config::default();
+        config.pipelines[0].led_horizontal_count = 100;
+        config.pipelines[0].led_vertical_count = 100;
+        config.pipelines[0].wled_type = WledType::Rgbw;
+        let raw = toml::Value::try_from(&config).unwrap();
+        assert_eq!(fields(&config, &raw), ["pipelines[0].wled_type"]);
+
+        config.pipelines[0].wled_type = WledType::Rgb;
+        assert_eq!(fields(&config, &raw), Vec::<String>::new());
+    }
+
+    #[test]
+    fn huge_led_counts_do_not_overflow() {
+        let mut config = Config::default();
+        config.pipelines[0].led_horizontal_count = u32::MAX;
+        config.pipelines[0].led_vertical_count = u32::MAX;
+        let raw = toml::Value::try_from(&config).unwrap();
+        assert_eq!(
+            fields(&config, &raw),
+            [
+                "pipelines[0].led_horizontal_count",
+                "pipelines[0].led_vertical_count"
+            ]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{sleep, spawn, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use super::{Config, ConfigError};
+use crate::processing::Lut3d;
+
+const POLL_PERIOD: Duration = Duration::from_secs(1);
+/// Editors often save in several writes, wait for them to finish before reading
+const SETTLE_PERIOD: Duration = Duration::from_millis(200);
+
+/// Polls config file and reports every change of its content, LUT files loaded by pipelines
+/// are polled too since their tables are not part of the config
+pub struct ConfigWatcher {
+    is_running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ConfigWatcher {
+    pub fn spawn<F, L>(path: PathBuf, on_change: F, on_lut_change: L) -> Self
+    where
+        F: Fn(Result<Config, ConfigError>) + Send + 'static,
+        L: Fn(Vec<PathBuf>) + Send + 'static,
+    {
+        let is_running = Arc::new(AtomicBool::new(true));
+        let thread = {
+            let is_running = is_running.clone();
+            spawn(move || {
+                let modified = |path: &PathBuf| fs::metadata(path).and_then(|m| m.modified()).ok();
+                let mut last_modified: Option<SystemTime> = modified(&path);
+                let mut last_content = fs::read_to_string(&path).ok();
+                let mut lut_modified = BTreeMap::new();
+
+                while is_running.load(Ordering::Relaxed) {
+                    sleep(POLL_PERIOD);
+
+                    // a file seen for the first time was just loaded, it has not changed yet
+                    let mut changed_luts = Vec::new();
+                    let lut_paths = Lut3d::shared_paths();
+                    for lut_path in &lut_paths {
+                        let current_modified = modified(lut_path);
+                        match lut_modified.insert(lut_path.clone(), current_modified) {
+                            Some(last_modified) if last_modified != current_modified => {
+                                changed_luts.push(lut_path.clone());
+                            }
+                            _ => {}
+                        }
+                    }
+                    lut_modified.retain(|lut_path, _| lut_paths.contains(lut_path));
+                    if !changed_luts.is_empty() {
+                        sleep(SETTLE_PERIOD);
+                        on_lut_change(changed_luts);
+                    }
+
+                    let current_modified = modified(&path);
+                    if current_modified == last_modified {
+                        continue;
+                    }
+                    last_modified = current_modified;
+
+                    sleep(SETTLE_PERIOD);
+                    let content = match fs::read_to_string(&path) {
+                        Ok(content) => content,
+                        Err(error) => {
+                            on_change(Err(ConfigError::Io {
+                                path: path.clone(),
+                                error,
+                            }));
+                            continue;
+                        }
+                    };
+                    if last_content.as_ref() == Some(&content) {
+                        continue;
+                    }
+
+                    on_change(Config::parse(&path, &content));
+                    last_content = Some(content);
+                }
+            })
+        };
+
+        Self {
+            is_running,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.is_running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().expect("could not stop config watcher");
+        }
+    }
+}
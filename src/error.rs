@@ -0,0 +1,42 @@
+use std::{fmt, io};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Failures of a running pipeline, `ColorSenderTask` retries transient ones and stops the
+/// pipeline on the rest
+#[derive(Debug)]
+pub enum Error {
+    /// Capture source has to be created again, e.g. after a display mode change, a driver
+    /// reset or while the display is unplugged
+    Capture(String),
+    /// Captured frame could not be turned into LED colors, e.g. an HDR frame format
+    Extraction(String),
+    /// Settings of the pipeline can't work on this machine
+    Config(String),
+    /// Colors could not be sent to WLED at `host`
+    Output { host: String, error: io::Error },
+}
+
+impl Error {
+    /// Transient errors may go away on their own, so the pipeline tries again later.
+    /// Others need the user to change something first.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Capture(_) | Error::Output { .. } => true,
+            Error::Extraction(_) | Error::Config(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Capture(message) => write!(f, "Screen capture failed: {}", message),
+            Error::Extraction(message) => write!(f, "Could not extract colors: {}", message),
+            Error::Config(message) => write!(f, "Invalid settings: {}", message),
+            Error::Output { host, error } => write!(f, "Could not reach WLED {}: {}", host, error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,330 @@
+use std::{
+    f32::consts::TAU,
+    time::{Duration, Instant},
+};
+
+use log::info;
+
+use crate::config::{FallbackConfig, FallbackEffect};
+
+/// LEDs on each side averaged into one LED of `FallbackEffect::HoldLast`
+const BLUR_RADIUS: usize = 3;
+/// `Breathing` never goes completely dark
+const BREATHING_FLOOR: f32 = 0.15;
+/// Candle flames flicker between this and full brightness
+const CANDLE_FLOOR: f32 = 0.55;
+/// Share of the way to its next flicker a candle LED moves every frame
+const CANDLE_SMOOTHING: f32 = 0.25;
+
+/// Renders a procedural effect while the capture delivers no frames and blends it with the
+/// captured colors, so the strip does not freeze on the last frame
+pub struct Fallback {
+    name: String,
+    config: FallbackConfig,
+    led_count: usize,
+    /// Colors of the last frame which was not stale
+    last_good: Vec<u8>,
+    /// Share of the effect in the colors, 0.0 shows captured colors only
+    mix: f32,
+    engaged: bool,
+    /// First time the effect was rendered, cycles of the effect start there
+    started: Option<Instant>,
+    last_update: Option<Instant>,
+    candle: Vec<f32>,
+    random: u32,
+}
+
+impl Fallback {
+    pub fn new(name: &str, config: FallbackConfig, led_count: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            config,
+            led_count,
+            last_good: Vec::new(),
+            mix: 0.0,
+            engaged: false,
+            started: None,
+            last_update: None,
+            candle: vec![1.0; led_count],
+            random: 0x9e37_79b9,
+        }
+    }
+
+    /// Frames this old are stale and replaced by the effect
+    pub fn is_stale(&self, frame_age: Duration) -> bool {
+        frame_age >= Duration::from_millis(self.config.after_ms as u64)
+    }
+
+    /// `colors` are processed captured colors or `None` before the first frame, `frame_age` is
+    /// how long ago the latest frame was captured
+    pub fn apply(&mut self, colors: Option<Vec<u8>>, frame_age: Duration, now: Instant) -> Vec<u8> {
+        let stale = self.is_stale(frame_age);
+        if stale != self.engaged {
+            self.engaged = stale;
+            if stale {
+                info!(
+                    pipeline = self.name;
+                    "No frame for {} ms, showing fallback effect {:?}",
+                    frame_age.as_millis(),
+                    self.config.effect
+                );
+            } else {
+                info!(pipeline = self.name; "Frames are back, fading out fallback effect");
+            }
+        }
+
+        let mut colors = colors
+            .filter(|colors| colors.len() == self.led_count * 4)
+            .unwrap_or_else(|| vec![0; self.led_count * 4]);
+        if !stale {
+            self.last_good.clone_from(&colors);
+        }
+
+        let elapsed = self
+            .last_update
+            .map_or(Duration::ZERO, |last_update| now - last_update);
+        self.last_update = Some(now);
+        let step = if self.config.fade_ms == 0 {
+            1.0
+        } else {
+            elapsed.as_secs_f32() * 1000.0 / self.config.fade_ms as f32
+        };
+        self.mix = if stale {
+            (self.mix + step).min(1.0)
+        } else {
+            (self.mix - step).max(0.0)
+        };
+        if self.mix == 0.0 {
+            return colors;
+        }
+
+        let effect = self.render(now);
+        for (color, effect) in colors.iter_mut().zip(effect) {
+            *color = (*color as f32 + (effect as f32 - *color as f32) * self.mix).round() as u8;
+        }
+        colors
+    }
+
+    /// RGBW of every LED
+    fn render(&mut self, now: Instant) -> Vec<u8> {
+        let started = *self.started.get_or_insert(now);
+        let cycle = (now - started).as_secs_f64() / self.config.period_seconds;
+        let cycle = cycle.fract() as f32;
+        let [r, g, b] = self.config.color.map(|channel| channel as f32);
+        let led_count = self.led_count;
+
+        match self.config.effect {
+            FallbackEffect::Rainbow => (0..led_count)
+                .flat_map(|led| {
+                    let hue = (cycle + led as f32 / led_count as f32).fract();
+                    let [r, g, b] = hue_to_rgb(hue);
+                    [r, g, b, 0]
+                })
+                .collect(),
+            FallbackEffect::Breathing => {
+                let level =
+                    BREATHING_FLOOR + (1.0 - BREATHING_FLOOR) * (0.5 - 0.5 * (cycle * TAU).cos());
+                let color = [r * level, g * level, b * level, 0.0].map(|c| c.round() as u8);
+                color.repeat(led_count)
+            }
+            FallbackEffect::Candle => {
+                let mut colors = Vec::with_capacity(led_count * 4);
+                for led in 0..led_count {
+                    let target = CANDLE_FLOOR + (1.0 - CANDLE_FLOOR) * self.next_random();
+                    let level = &mut self.candle[led];
+                    *level += (target - *level) * CANDLE_SMOOTHING;
+                    let level = *level;
+                    colors.extend([r * level, g * level, b * level, 0.0].map(|c| c.round() as u8));
+                }
+                colors
+            }
+            FallbackEffect::HoldLast => blur(&self.last_good, led_count),
+        }
+    }
+
+    /// Xorshift, flicker does not need anything better, 0.0-1.0
+    fn next_random(&mut self) -> f32 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 17;
+        self.random ^= self.random << 5;
+        self.random as f32 / u32::MAX as f32
+    }
+}
+
+/// Fully saturated color of `hue` (0.0-1.0)
+fn hue_to_rgb(hue: f32) -> [u8; 3] {
+    let sector = hue * 6.0;
+    let rising = (sector.fract() * 255.0).round() as u8;
+    let falling = 255 - rising;
+    match sector as u32 {
+        0 => [255, rising, 0],
+        1 => [falling, 255, 0],
+        2 => [0, 255, rising],
+        3 => [0, falling, 255],
+        4 => [rising, 0, 255],
+        _ => [255, 0, falling],
+    }
+}
+
+/// Box blur of RGBW `colors`, the strip goes around the screen so its ends are neighbors
+fn blur(colors: &[u8], led_count: usize) -> Vec<u8> {
+    if colors.len() != led_count * 4 || led_count == 0 {
+        return vec![0; led_count * 4];
+    }
+    let window = (2 * BLUR_RADIUS + 1) as u32;
+    (0..led_count)
+        .flat_map(|led| {
+            let mut sum = [0u32; 4];
+            for offset in 0..window as usize {
+                let neighbor = (led + led_count * BLUR_RADIUS + offset - BLUR_RADIUS) % led_count;
+                for (channel, sum) in sum.iter_mut().enumerate() {
+                    *sum += colors[neighbor * 4 + channel] as u32;
+                }
+            }
+            sum.map(|sum| (sum / window) as u8)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LED_COUNT: usize = 10;
+
+    fn fallback(effect: FallbackEffect) -> Fallback {
+        let config = FallbackConfig {
+            after_ms: 1000,
+            effect,
+            color: [200, 100, 0],
+            period_seconds: 10.0,
+            fade_ms: 100,
+        };
+        Fallback::new("test", config, LED_COUNT)
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// Red channel of every LED
+    fn reds(colors: &[u8]) -> Vec<u8> {
+        colors.chunks_exact(4).map(|led| led[0]).collect()
+    }
+
+    #[test]
+    fn frames_become_stale_after_after_ms() {
+        let fallback = fallback(FallbackEffect::HoldLast);
+        assert!(!fallback.is_stale(ms(999)));
+        assert!(fallback.is_stale(ms(1000)));
+    }
+
+    #[test]
+    fn effect_fades_in_while_stale_and_out_once_frames_are_back() {
+        let mut fallback = fallback(FallbackEffect::Breathing);
+        let start = Instant::now();
+        let frame = || Some(vec![100; LED_COUNT * 4]);
+
+        let fresh = fallback.apply(frame(), ms(0), start);
+        assert_eq!(reds(&fresh), [100; LED_COUNT]);
+        // breathing starts at its floor, 200 * 0.15 = 30, half mixed into 100
+        let half = fallback.apply(frame(), ms(1000), start + ms(50));
+        assert_eq!(reds(&half), [65; LED_COUNT]);
+        let full = fallback.apply(frame(), ms(1050), start + ms(100));
+        assert_eq!(reds(&full), [30; LED_COUNT]);
+        let full = fallback.apply(frame(), ms(2000), start + ms(1000));
+        assert!(reds(&full).iter().all(|&red| red > 30));
+
+        let back = fallback.apply(frame(), ms(0), start + ms(1050));
+        assert!(reds(&back).iter().all(|&red| red > 65 && red < 100));
+        let back = fallback.apply(frame(), ms(0), start + ms(1100));
+        assert_eq!(back, vec![100; LED_COUNT * 4]);
+    }
+
+    #[test]
+    fn blur_wraps_around_strip_ends() {
+        let mut colors = vec![0; LED_COUNT * 4];
+        colors[..4].copy_from_slice(&[70, 140, 210, 7]);
+
+        let blurred = blur(&colors, LED_COUNT);
+        // 7 LEDs wide window, LEDs up to 3 away on both sides of the first one get a share
+        assert_eq!(reds(&blurred), [10, 10, 10, 10, 0, 0, 0, 10, 10, 10]);
+        assert_eq!(&blurred[..4], [10, 20, 30, 1]);
+        assert_eq!(blur(&colors[..8], LED_COUNT), vec![0; LED_COUNT * 4]);
+    }
+
+    #[test]
+    fn hold_last_shows_blurred_last_fresh_frame() {
+        let mut fallback = fallback(FallbackEffect::HoldLast);
+        let start = Instant::now();
+        let mut frame = vec![0; LED_COUNT * 4];
+        frame[4 * 5] = 70;
+
+        fallback.apply(Some(frame.clone()), ms(0), start);
+        // the stale frame is replaced by the blurred last fresh one
+        let held = fallback.apply(Some(vec![255; LED_COUNT * 4]), ms(1000), start + ms(100));
+        assert_eq!(held, blur(&frame, LED_COUNT));
+    }
+
+    #[test]
+    fn rainbow_spreads_hues_over_strip_and_moves_them() {
+        let mut fallback = fallback(FallbackEffect::Rainbow);
+        let start = Instant::now();
+
+        let colors = fallback.render(start);
+        for led in colors.chunks_exact(4) {
+            assert_eq!(led[..3].iter().max(), Some(&255));
+            assert_eq!(led[..3].iter().min(), Some(&0));
+            assert_eq!(led[3], 0);
+        }
+        assert_eq!(colors[..4], [255, 0, 0, 0]);
+        // half of the strip is half of the hue circle
+        assert_eq!(colors[5 * 4..5 * 4 + 4], [0, 255, 255, 0]);
+
+        let later = fallback.render(start + Duration::from_secs(5));
+        assert_eq!(later[..4], [0, 255, 255, 0]);
+    }
+
+    #[test]
+    fn breathing_goes_from_floor_to_full_color() {
+        let mut fallback = fallback(FallbackEffect::Breathing);
+        let start = Instant::now();
+
+        assert_eq!(fallback.render(start)[..4], [30, 15, 0, 0]);
+        let peak = fallback.render(start + Duration::from_secs(5));
+        assert_eq!(peak, [200, 100, 0, 0].repeat(LED_COUNT));
+        assert_eq!(
+            fallback.render(start + Duration::from_secs(10))[..4],
+            [30, 15, 0, 0]
+        );
+    }
+
+    #[test]
+    fn candle_flickers_between_floor_and_full_color() {
+        let mut fallback = fallback(FallbackEffect::Candle);
+        let start = Instant::now();
+
+        let mut reds_seen = Vec::new();
+        for frame in 0..50 {
+            let colors = fallback.render(start + ms(frame * 16));
+            for led in colors.chunks_exact(4) {
+                assert!(led[0] >= (200.0 * CANDLE_FLOOR) as u8);
+                // hue of the flame stays
+                assert!(led[1].abs_diff(led[0] / 2) <= 1);
+                assert_eq!(led[2..], [0, 0]);
+            }
+            reds_seen.extend(reds(&colors));
+        }
+        reds_seen.sort_unstable();
+        reds_seen.dedup();
+        assert!(reds_seen.len() > 10, "{:?}", reds_seen);
+    }
+
+    #[test]
+    fn hues_go_around_color_wheel() {
+        assert_eq!(hue_to_rgb(0.0), [255, 0, 0]);
+        assert_eq!(hue_to_rgb(0.25), [127, 255, 0]);
+        assert_eq!(hue_to_rgb(0.5), [0, 255, 255]);
+        assert_eq!(hue_to_rgb(0.75), [128, 0, 255]);
+    }
+}
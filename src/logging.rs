@@ -0,0 +1,383 @@
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{
+    kv::{self, Key, Value, VisitSource},
+    Level, LevelFilter, Log, Metadata, Record,
+};
+
+use crate::config::{LogLevel, LoggingConfig};
+
+/// Identical warnings and errors are written at most once per this period, a disconnected
+/// WLED would fill the disk otherwise
+const REPEAT_PERIOD: Duration = Duration::from_secs(60);
+
+static LOGGER: Logger = Logger {
+    state: Mutex::new(None),
+};
+
+/// Writes records as `key=value` pairs (logfmt) to stdout or a rotated file
+struct Logger {
+    /// `None` until `init`, statics can't hold a `HashMap` from the start
+    state: Mutex<Option<State>>,
+}
+
+struct State {
+    output: Output,
+    repeated: RepeatFilter,
+}
+
+/// Leaves out lines already written within `REPEAT_PERIOD`
+#[derive(Default)]
+struct RepeatFilter {
+    /// Last time a line was written and how many were left out since, keyed on the line
+    /// without its time
+    written: HashMap<String, (Instant, u32)>,
+}
+
+enum Output {
+    Stdout,
+    File(RotatingFile),
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    /// Rotated files kept next to the current one as `<path>.1`, `<path>.2`, ...
+    max_files: u32,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut line = format!(
+            "time={} level={}",
+            timestamp(),
+            record.level().as_str().to_lowercase()
+        );
+        let mut fields = Fields(&mut line);
+        // formatting into a String never fails
+        let _ = record.key_values().visit(&mut fields);
+        push_pair(&mut line, "msg", &record.args().to_string());
+
+        let mut state = self.state.lock().unwrap();
+        let Some(state) = state.as_mut() else {
+            return;
+        };
+
+        if record.level() <= Level::Warn {
+            match state.repeated.admit(&line, Instant::now()) {
+                None => return,
+                Some(0) => {}
+                Some(skipped) => push_pair(&mut line, "repeated", &skipped.to_string()),
+            }
+        }
+
+        line.push('\n');
+        // there is nowhere else to report a failed write
+        let _ = state.output.write(line.as_bytes());
+    }
+
+    fn flush(&self) {
+        if let Some(state) = self.state.lock().unwrap().as_mut() {
+            let _ = state.output.flush();
+        }
+    }
+}
+
+impl RepeatFilter {
+    /// Number of times `line` was left out since it was written last, `None` leaves it out
+    fn admit(&mut self, line: &str, now: Instant) -> Option<u32> {
+        // the time differs in every line, the rest has to be the same
+        let key = line.split_once(' ').map(|(_, rest)| rest).unwrap_or(line);
+        match self.written.get_mut(key) {
+            Some((last, skipped)) if now.duration_since(*last) < REPEAT_PERIOD => {
+                *skipped += 1;
+                None
+            }
+            Some((last, skipped)) => {
+                let repeated = *skipped;
+                *last = now;
+                *skipped = 0;
+                Some(repeated)
+            }
+            None => {
+                self.written
+                    .retain(|_, (last, _)| now.duration_since(*last) < REPEAT_PERIOD);
+                self.written.insert(key.to_string(), (now, 0));
+                Some(0)
+            }
+        }
+    }
+}
+
+impl Output {
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        match self {
+            Output::Stdout => {
+                let mut stdout = io::stdout();
+                stdout.write_all(line)?;
+                stdout.flush()
+            }
+            Output::File(file) => file.write(line),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout => io::stdout().flush(),
+            Output::File(file) => file.file.flush(),
+        }
+    }
+}
+
+impl RotatingFile {
+    fn open(path: &Path, config: &LoggingConfig) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            size: file.metadata()?.len(),
+            file,
+            max_size: config.max_file_size_kb * 1024,
+            max_files: config.max_files,
+        })
+    }
+
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |index: u32| PathBuf::from(format!("{}.{}", self.path.display(), index));
+
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                if rotated(index).exists() {
+                    fs::rename(rotated(index), rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Appends fields of the record, e.g. `pipeline="Main display"`
+struct Fields<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        push_pair(self.0, key.as_str(), &value.to_string());
+        Ok(())
+    }
+}
+
+fn push_pair(line: &mut String, key: &str, value: &str) {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '=' || c == '"' || c == '\\');
+    if needs_quotes {
+        let escaped = value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        let _ = write!(line, " {}=\"{}\"", key, escaped);
+    } else {
+        let _ = write!(line, " {}={}", key, value);
+    }
+}
+
+/// Installs the logger writing info and above to stdout, so problems with loading the config
+/// are visible before `configure` is called
+pub fn init() {
+    *LOGGER.state.lock().unwrap() = Some(State {
+        output: Output::Stdout,
+        repeated: RepeatFilter::default(),
+    });
+    log::set_logger(&LOGGER).expect("logger is initialized only once");
+    log::set_max_level(LevelFilter::Info);
+}
+
+/// Applies level and output of `config`, `file` is the log file already resolved from the
+/// command line or the config, `None` logs to stdout
+pub fn configure(config: &LoggingConfig, file: Option<&Path>) -> io::Result<()> {
+    let output = match file {
+        Some(path) => Output::File(RotatingFile::open(path, config)?),
+        None => Output::Stdout,
+    };
+
+    if let Some(state) = LOGGER.state.lock().unwrap().as_mut() {
+        state.output = output;
+    }
+    log::set_max_level(match config.level {
+        LogLevel::Error => LevelFilter::Error,
+        LogLevel::Warn => LevelFilter::Warn,
+        LogLevel::Info => LevelFilter::Info,
+        LogLevel::Debug => LevelFilter::Debug,
+        LogLevel::Trace => LevelFilter::Trace,
+    });
+
+    Ok(())
+}
+
+/// UTC time as `YYYY-MM-DDTHH:MM:SSZ`
+fn timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let time = seconds % 86400;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Date of a day since 1970-01-01, http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "wled_ambilight_log_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// 1 kB files, every line fills more than half of one
+    fn write_lines(path: &Path, max_files: u32, lines: &[char]) {
+        let config = LoggingConfig {
+            max_file_size_kb: 1,
+            max_files,
+            ..Default::default()
+        };
+        let mut file = RotatingFile::open(path, &config).unwrap();
+        for &line in lines {
+            file.write(format!("{}\n", line.to_string().repeat(600)).as_bytes())
+                .unwrap();
+        }
+    }
+
+    fn first_char(path: &Path) -> Option<char> {
+        fs::read_to_string(path).ok()?.chars().next()
+    }
+
+    #[test]
+    fn full_file_is_rotated_and_oldest_is_dropped() {
+        let dir = log_dir("rotation");
+        let path = dir.join("app.log");
+        write_lines(&path, 2, &['a', 'b', 'c', 'd']);
+
+        let rotated = |index: u32| dir.join(format!("app.log.{}", index));
+        assert_eq!(first_char(&path), Some('d'));
+        assert_eq!(first_char(&rotated(1)), Some('c'));
+        assert_eq!(first_char(&rotated(2)), Some('b'));
+        assert!(!rotated(3).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn full_file_is_deleted_without_rotated_files() {
+        let dir = log_dir("no_rotation");
+        let path = dir.join("app.log");
+        write_lines(&path, 0, &['a', 'b']);
+
+        assert_eq!(fs::read_to_string(&path).unwrap().len(), 601);
+        assert_eq!(first_char(&path), Some('b'));
+        assert!(!dir.join("app.log.1").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn repeated_lines_are_left_out_for_a_while() {
+        let mut filter = RepeatFilter::default();
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+
+        assert_eq!(filter.admit("time=1 level=warn msg=lost", at(0)), Some(0));
+        // same line at another time
+        assert_eq!(filter.admit("time=2 level=warn msg=lost", at(10)), None);
+        assert_eq!(filter.admit("time=3 level=warn msg=lost", at(59)), None);
+        assert_eq!(filter.admit("time=3 level=warn msg=found", at(59)), Some(0));
+        assert_eq!(filter.admit("time=4 level=warn msg=lost", at(60)), Some(2));
+        assert_eq!(filter.admit("time=5 level=warn msg=lost", at(61)), None);
+        assert_eq!(filter.admit("time=6 level=warn msg=lost", at(200)), Some(1));
+    }
+
+    #[test]
+    fn days_are_turned_into_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(59), (1970, 3, 1));
+        // leap day of a year divisible by 400
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+        assert_eq!(civil_from_days(19723), (2024, 1, 1));
+        // 2100 is not a leap year
+        assert_eq!(civil_from_days(47540), (2100, 2, 28));
+        assert_eq!(civil_from_days(47541), (2100, 3, 1));
+    }
+}
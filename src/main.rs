@@ -1,58 +1,621 @@
 // #![windows_subsystem = "windows"]
 
+mod api;
+mod cli;
 mod color_sender_task;
 mod config;
+mod error;
+mod fallback;
+mod logging;
+mod metrics;
+mod pacing;
+mod preview;
+mod processing;
+mod recording;
+mod render;
 mod screen;
+mod standby;
 
-use color_sender_task::ColorSenderTask;
-use config::Config;
-use {std::sync::mpsc, tray_item::TrayItem};
+use api::ApiServer;
+use cli::Args;
+use color_sender_task::{ColorSenderTask, SenderContext};
+use config::{ApiConfig, Config, ConfigError, ConfigWatcher, LoggingConfig, PipelineConfig};
+use log::{error, info, warn};
+use metrics::Metrics;
+use preview::Preview;
+use processing::Lut3d;
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Sender},
+        Arc,
+    },
+};
+#[cfg(windows)]
+use {
+    color_sender_task::PipelineState,
+    config::Profile,
+    tray_item::TrayItem,
+    windows::{
+        core::HSTRING,
+        Win32::{
+            Foundation::HWND,
+            UI::WindowsAndMessaging::{MessageBoxW, MB_ICONERROR, MB_OK},
+        },
+    },
+};
 
 enum Message {
-    Start,
-    Stop,
+    StartAll,
+    StopAll,
+    /// Pipelines are referred to by name, indices change when config is reloaded
+    Start(String),
+    Stop(String),
+    Reload(Result<Config, ConfigError>),
+    /// LUT files in use were edited, the config itself stays the same
+    LutChanged(Vec<PathBuf>),
+    /// `None` switches back to pipelines without any profile
+    SetProfile(Option<String>),
+    /// Replaces brightness of all pipelines, `None` goes back to brightness of the config
+    SetBrightness(Option<f64>),
+    /// Dims every pipeline and saves the value into the config file
+    SetMasterBrightness(f64),
+    /// Asks for a snapshot of the current state, sent by the HTTP API
+    Status(Sender<api::Status>),
+    /// Pipeline started retrying after an error or got back to running
+    StateChanged,
+    /// Pipeline stopped on an error it can't recover from
+    Failed {
+        pipeline: String,
+        error: String,
+    },
     Quit,
 }
 
 fn main() {
-    let config = Config::load();
-    let mut tray = TrayItem::new("WLED Ambilight", "tray-icon").unwrap();
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+    logging::init();
 
-    tray.add_label("WLED Ambilight").unwrap();
+    let config_path = config::locate(args.config.as_deref());
+    // --log-file gets problems with loading the config too
+    configure_logging(&LoggingConfig::default(), &config_path, &args);
+    let mut config = match Config::load(&config_path) {
+        Ok(config) => config,
+        Err(err) => {
+            show_fatal_error(&err.to_string(), args.headless);
+            return;
+        }
+    };
+    configure_logging(&config.logging, &config_path, &args);
+    let mut active_profile = args.profile.clone().or(config.active_profile.clone());
+    if let Some(frames) = &args.render {
+        if let Err(err) = render_frames(frames, &config, active_profile.as_deref(), &args) {
+            error!("{}", err);
+        }
+        return;
+    }
+    if let Some(master_brightness) = args.master_brightness {
+        match config.master_brightness_variable() {
+            Some(variable) => warn!("{}", master_brightness_overridden(variable)),
+            None => {
+                config.master_brightness = master_brightness;
+                save_master_brightness(&config_path, master_brightness);
+            }
+        }
+    }
+    let mut brightness = None;
+    let pipelines = match pipelines_for(&config, active_profile.as_deref(), brightness, &args) {
+        Ok(pipelines) => pipelines,
+        Err(err) => {
+            show_fatal_error(&err, args.headless);
+            return;
+        }
+    };
 
     let (tx, rx) = mpsc::channel();
+    let context = SenderContext {
+        dry_run: args.dry_run,
+        preview: Arc::new(Preview::default()),
+        metrics: Arc::new(Metrics::default()),
+        record: args.record.clone(),
+        replay: args.replay.clone(),
+        tx: tx.clone(),
+    };
+
+    let mut senders: Vec<ColorSenderTask> = pipelines
+        .into_iter()
+        .map(|pipeline| ColorSenderTask::new(pipeline, context.clone()))
+        .collect();
+    retain_used_luts(&senders);
+
+    // services and containers stop the app with a signal, senders have to release WLED
+    let signal_tx = tx.clone();
+    if let Err(err) = ctrlc::set_handler(move || {
+        info!("Termination signal received, stopping");
+        let _ = signal_tx.send(Message::Quit);
+    }) {
+        warn!("Could not handle termination signals: {}", err);
+    }
+
+    // the tray icon lives as long as this binding
+    #[cfg(windows)]
+    let mut tray = if args.headless {
+        None
+    } else {
+        Some(create_tray(&tx, &senders, &config.profiles))
+    };
+    #[cfg(windows)]
+    let mut tray_labels_shown = tray_labels(&senders);
+    if args.headless {
+        senders.iter_mut().for_each(ColorSenderTask::start);
+    }
+
+    let mut api = spawn_api(&config.api, &tx, &context, args.headless);
+
+    let reload_tx = tx.clone();
+    let lut_tx = tx.clone();
+    let mut watcher = ConfigWatcher::spawn(
+        config_path.clone(),
+        move |config| {
+            // the receiver is gone only after the main loop ended
+            let _ = reload_tx.send(Message::Reload(config));
+        },
+        move |paths| {
+            let _ = lut_tx.send(Message::LutChanged(paths));
+        },
+    );
+
+    loop {
+        match rx.recv() {
+            Ok(Message::Quit) => break,
+            Ok(Message::StartAll) => {
+                senders.iter_mut().for_each(ColorSenderTask::start);
+            }
+            // pipelines fade out on their own threads, the main loop does not wait for them
+            Ok(Message::StopAll) => {
+                senders.iter_mut().for_each(ColorSenderTask::request_stop);
+            }
+            Ok(Message::Start(name)) => {
+                if let Some(sender) = senders.iter_mut().find(|s| s.name() == name) {
+                    sender.start();
+                }
+            }
+            Ok(Message::Stop(name)) => {
+                if let Some(sender) = senders.iter_mut().find(|s| s.name() == name) {
+                    sender.request_stop();
+                }
+            }
+            Ok(Message::Reload(Ok(new_config))) => {
+                info!("Config changed, applying");
+                // selected profile survives the reload unless it was removed
+                if !new_config
+                    .profiles
+                    .iter()
+                    .any(|p| Some(&p.name) == active_profile.as_ref())
+                {
+                    active_profile = new_config.active_profile.clone();
+                }
+                match pipelines_for(&new_config, active_profile.as_deref(), brightness, &args) {
+                    Ok(pipelines) => {
+                        apply_pipelines(&mut senders, pipelines, &context);
+                        if new_config.api != config.api {
+                            if let Some(api) = &mut api {
+                                api.stop();
+                            }
+                            api = spawn_api(&new_config.api, &tx, &context, args.headless);
+                        }
+                        if new_config.logging != config.logging {
+                            configure_logging(&new_config.logging, &config_path, &args);
+                        }
+                        config = new_config;
+                    }
+                    Err(err) => show_error(
+                        &format!("{}\n\nKeeping previous settings.", err),
+                        args.headless,
+                    ),
+                }
+            }
+            Ok(Message::LutChanged(paths)) => {
+                // a broken file keeps the previously loaded table
+                let valid: Vec<PathBuf> = paths
+                    .into_iter()
+                    .filter(|path| match Lut3d::load_shared(path) {
+                        Ok(_) => true,
+                        Err(err) => {
+                            show_error(
+                                &format!(
+                                    "Could not load LUT {}: {}\n\nKeeping previous table.",
+                                    path.display(),
+                                    err
+                                ),
+                                args.headless,
+                            );
+                            false
+                        }
+                    })
+                    .collect();
+                if !valid.is_empty() {
+                    info!("LUT changed, applying");
+                    senders.iter_mut().for_each(|s| s.reload_lut(&valid));
+                }
+            }
+            Ok(Message::Reload(Err(err))) => {
+                show_error(
+                    &format!("{}\n\nKeeping previous settings.", err),
+                    args.headless,
+                );
+            }
+            Ok(Message::SetProfile(profile)) => {
+                match pipelines_for(&config, profile.as_deref(), brightness, &args) {
+                    Ok(pipelines) => {
+                        info!("Switching to profile {:?}", profile);
+                        apply_pipelines(&mut senders, pipelines, &context);
+                        active_profile = profile;
+                    }
+                    Err(err) => show_error(&err, args.headless),
+                }
+            }
+            Ok(Message::SetBrightness(new_brightness)) => {
+                match pipelines_for(&config, active_profile.as_deref(), new_brightness, &args) {
+                    Ok(pipelines) => {
+                        info!("Setting brightness to {:?}", new_brightness);
+                        apply_pipelines(&mut senders, pipelines, &context);
+                        brightness = new_brightness;
+                    }
+                    Err(err) => show_error(&err, args.headless),
+                }
+            }
+            // the variable would win again on the next reload
+            Ok(Message::SetMasterBrightness(_))
+                if config.master_brightness_variable().is_some() =>
+            {
+                let variable = config.master_brightness_variable().unwrap_or_default();
+                show_error(&master_brightness_overridden(variable), args.headless);
+            }
+            Ok(Message::SetMasterBrightness(master_brightness)) => {
+                info!(
+                    "Setting master brightness to {:.0} %",
+                    master_brightness * 100.0
+                );
+                config.master_brightness = master_brightness;
+                match pipelines_for(&config, active_profile.as_deref(), brightness, &args) {
+                    Ok(pipelines) => apply_pipelines(&mut senders, pipelines, &context),
+                    Err(err) => show_error(&err, args.headless),
+                }
+                save_master_brightness(&config_path, master_brightness);
+            }
+            Ok(Message::Status(reply)) => {
+                let profiles = config.profiles.iter().map(|p| p.name.clone()).collect();
+                // the API gave up waiting when nobody receives the reply
+                let _ = reply.send(api::Status::new(
+                    &senders,
+                    active_profile.as_deref(),
+                    profiles,
+                    brightness,
+                    config.master_brightness,
+                    config.master_brightness_variable(),
+                ));
+            }
+            Ok(Message::Failed { pipeline, error }) => {
+                show_error(
+                    &format!(
+                        "{} stopped: {}\n\nStart it again once the problem is fixed.",
+                        pipeline, error
+                    ),
+                    args.headless,
+                );
+            }
+            _ => {}
+        }
+
+        // tray-item can't change labels, the menu is built again to show pipeline states
+        #[cfg(windows)]
+        if let Some(tray) = &mut tray {
+            let labels = tray_labels(&senders);
+            if labels != tray_labels_shown {
+                *tray = create_tray(&tx, &senders, &config.profiles);
+                tray_labels_shown = labels;
+            }
+        }
+    }
+
+    if let Some(api) = &mut api {
+        api.stop();
+    }
+    watcher.stop();
+    stop_all(&mut senders);
+}
+
+/// Every pipeline fades out at the same time, then all of them are waited for
+fn stop_all(senders: &mut [ColorSenderTask]) {
+    senders.iter_mut().for_each(ColorSenderTask::request_stop);
+    senders.iter_mut().for_each(ColorSenderTask::stop);
+}
+
+#[cfg(windows)]
+fn create_tray(
+    tx: &Sender<Message>,
+    senders: &[ColorSenderTask],
+    profiles: &[Profile],
+) -> TrayItem {
+    let mut tray = TrayItem::new("WLED Ambilight", "tray-icon").unwrap();
+
+    // a single pipeline has no label of its own
+    match senders {
+        [sender] => tray
+            .add_label(&tray_label("WLED Ambilight", sender.status().state))
+            .unwrap(),
+        _ => tray.add_label("WLED Ambilight").unwrap(),
+    }
 
     let start_tx = tx.clone();
     tray.add_menu_item("Start", move || {
-        start_tx.send(Message::Start).unwrap();
+        start_tx.send(Message::StartAll).unwrap();
     })
     .unwrap();
 
     let stop_tx = tx.clone();
     tray.add_menu_item("Stop", move || {
-        stop_tx.send(Message::Stop).unwrap();
+        stop_tx.send(Message::StopAll).unwrap();
     })
     .unwrap();
 
+    // individual controls only make sense with more than one pipeline
+    if senders.len() > 1 {
+        for sender in senders {
+            tray.add_label(&tray_label(sender.name(), sender.status().state))
+                .unwrap();
+
+            let start_tx = tx.clone();
+            let name = sender.name().to_string();
+            tray.add_menu_item(&format!("Start {}", sender.name()), move || {
+                start_tx.send(Message::Start(name.clone())).unwrap();
+            })
+            .unwrap();
+
+            let stop_tx = tx.clone();
+            let name = sender.name().to_string();
+            tray.add_menu_item(&format!("Stop {}", sender.name()), move || {
+                stop_tx.send(Message::Stop(name.clone())).unwrap();
+            })
+            .unwrap();
+        }
+    }
+
+    // tray-item has no submenus, profiles are listed under their own label
+    if !profiles.is_empty() {
+        tray.add_label("Profiles").unwrap();
+
+        let profile_tx = tx.clone();
+        tray.add_menu_item("No profile", move || {
+            profile_tx.send(Message::SetProfile(None)).unwrap();
+        })
+        .unwrap();
+
+        for profile in profiles {
+            let profile_tx = tx.clone();
+            let name = profile.name.clone();
+            tray.add_menu_item(&profile.name, move || {
+                profile_tx
+                    .send(Message::SetProfile(Some(name.clone())))
+                    .unwrap();
+            })
+            .unwrap();
+        }
+    }
+
+    tray.add_label("Brightness").unwrap();
+    for percent in (10..=100).rev().step_by(10) {
+        let brightness_tx = tx.clone();
+        tray.add_menu_item(&format!("{} %", percent), move || {
+            brightness_tx
+                .send(Message::SetMasterBrightness(percent as f64 / 100.0))
+                .unwrap();
+        })
+        .unwrap();
+    }
+
     let quit_tx = tx.clone();
     tray.add_menu_item("Quit", move || {
-        println!("Quit");
+        info!("Quit");
         quit_tx.send(Message::Quit).unwrap();
     })
     .unwrap();
 
-    let mut sender = ColorSenderTask::new(config);
+    tray
+}
 
-    loop {
-        match rx.recv() {
-            Ok(Message::Quit) => break,
-            Ok(Message::Start) => {
-                sender.start();
+#[cfg(windows)]
+fn tray_label(name: &str, state: PipelineState) -> String {
+    match state {
+        PipelineState::Retrying => format!("{} (retrying)", name),
+        PipelineState::Failed => format!("{} (failed)", name),
+        _ => name.to_string(),
+    }
+}
+
+/// Labels of pipelines in the tray, the menu is built again once they change
+#[cfg(windows)]
+fn tray_labels(senders: &[ColorSenderTask]) -> Vec<String> {
+    senders
+        .iter()
+        .map(|sender| tray_label(sender.name(), sender.status().state))
+        .collect()
+}
+
+/// Pipelines of `config` with `profile`, brightness set through the API and command line
+/// overrides applied
+fn pipelines_for(
+    config: &Config,
+    profile: Option<&str>,
+    brightness: Option<f64>,
+    args: &Args,
+) -> Result<Vec<PipelineConfig>, String> {
+    let mut pipelines = config.pipelines_with_profile(profile)?;
+    for pipeline in &mut pipelines {
+        if let Some(brightness) = brightness {
+            pipeline.adjustments.brightness = brightness;
+            if let Some(dark_scene) = &mut pipeline.dark_scene {
+                dark_scene.adjustments.brightness = brightness;
             }
-            Ok(Message::Stop) => {
-                sender.stop();
+        }
+        if let Some(target) = &args.target {
+            pipeline.wled_ip = target.clone();
+        }
+        pipeline.master_brightness = config.master_brightness;
+    }
+    Ok(pipelines)
+}
+
+/// `render` command, frames go through the pipeline selected by `--pipeline` or the first one
+fn render_frames(
+    frames: &Path,
+    config: &Config,
+    profile: Option<&str>,
+    args: &Args,
+) -> Result<(), String> {
+    let pipelines = pipelines_for(config, profile, None, args)?;
+    let pipeline = match &args.pipeline {
+        Some(name) => pipelines
+            .iter()
+            .find(|p| &p.name == name)
+            .ok_or_else(|| format!("there is no pipeline `{}`", name))?,
+        None => pipelines.first().ok_or("config has no pipelines")?,
+    };
+    render::render(
+        frames,
+        pipeline,
+        args.timeline
+            .as_deref()
+            .unwrap_or(Path::new("timeline.csv")),
+        args.strip.as_deref().unwrap_or(Path::new("strip.png")),
+    )
+}
+
+/// Logs into `--log-file` or the file of `config`, which is relative to the config file
+fn configure_logging(config: &LoggingConfig, config_path: &Path, args: &Args) {
+    let file = args.log_file.clone().or_else(|| {
+        let file = config.file.as_ref()?;
+        Some(config_path.parent().unwrap_or(Path::new("")).join(file))
+    });
+    if let Err(err) = logging::configure(config, file.as_deref()) {
+        show_error(&format!("Could not open log file: {}", err), args.headless);
+    }
+}
+
+/// Server of the HTTP API when it is enabled, the app keeps running when it can't start
+fn spawn_api(
+    config: &Option<ApiConfig>,
+    tx: &Sender<Message>,
+    context: &SenderContext,
+    headless: bool,
+) -> Option<ApiServer> {
+    let api = config.as_ref()?;
+    match ApiServer::spawn(
+        &api.address,
+        tx.clone(),
+        context.preview.clone(),
+        context.metrics.clone(),
+    ) {
+        Ok(server) => Some(server),
+        Err(err) => {
+            show_error(&err, headless);
+            None
+        }
+    }
+}
+
+/// Updates pipelines matched by name, removed pipelines are stopped and new ones are started
+/// when anything else is running. Tray menu keeps items of the pipelines it was built with.
+fn apply_pipelines(
+    senders: &mut Vec<ColorSenderTask>,
+    pipelines: Vec<PipelineConfig>,
+    context: &SenderContext,
+) {
+    let any_running = senders.iter().any(ColorSenderTask::is_running);
+
+    let mut updated = Vec::with_capacity(pipelines.len());
+    for pipeline in pipelines {
+        match senders.iter().position(|s| s.name() == pipeline.name) {
+            Some(index) => {
+                let mut sender = senders.swap_remove(index);
+                sender.update(pipeline);
+                updated.push(sender);
+            }
+            None => {
+                let mut sender = ColorSenderTask::new(pipeline, context.clone());
+                if any_running {
+                    sender.start();
+                }
+                updated.push(sender);
             }
-            _ => {}
         }
     }
+
+    stop_all(senders);
+    *senders = updated;
+    retain_used_luts(senders);
+}
+
+/// Validation loaded LUTs of every profile, only those of current pipelines stay loaded
+fn retain_used_luts(senders: &[ColorSenderTask]) {
+    let lut_paths: Vec<PathBuf> = senders
+        .iter()
+        .filter_map(ColorSenderTask::lut_path)
+        .collect();
+    Lut3d::retain_shared(&lut_paths);
+}
+
+/// Brightness still applies when it can't be saved, it is just lost on restart
+fn master_brightness_overridden(variable: &str) -> String {
+    format!(
+        "Master brightness is set by environment variable {}, unset it to change brightness",
+        variable
+    )
+}
+
+fn save_master_brightness(config_path: &Path, master_brightness: f64) {
+    if let Err(err) = Config::save_master_brightness(config_path, master_brightness) {
+        warn!("Could not save master brightness: {}", err);
+    }
+}
+
+/// Without a console window errors would disappear, so they are shown in a message box too.
+/// The box is modal, it gets its own thread so the tray and API keep working while it is open.
+#[cfg_attr(not(windows), allow(unused_variables))]
+fn show_error(message: &str, headless: bool) {
+    error!("{}", message);
+    #[cfg(windows)]
+    if !headless {
+        let message = message.to_string();
+        std::thread::spawn(move || message_box(&message));
+    }
+}
+
+/// Error the app exits on, returns once the message box is closed
+#[cfg_attr(not(windows), allow(unused_variables))]
+fn show_fatal_error(message: &str, headless: bool) {
+    error!("{}", message);
+    #[cfg(windows)]
+    if !headless {
+        message_box(message);
+    }
+}
+
+#[cfg(windows)]
+fn message_box(message: &str) {
+    unsafe {
+        MessageBoxW(
+            HWND(0),
+            &HSTRING::from(message),
+            &HSTRING::from("WLED Ambilight"),
+            MB_OK | MB_ICONERROR,
+        );
+    }
 }
@@ -0,0 +1,349 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// Upper bounds of histogram buckets in seconds, frames take from under a millisecond
+/// to a whole refresh period
+const BUCKETS: [f64; 10] = [
+    0.0005, 0.001, 0.002, 0.004, 0.008, 0.016, 0.033, 0.066, 0.125, 0.25,
+];
+
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn increment(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Floating point value stored as its bits
+#[derive(Default)]
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Default)]
+pub struct Histogram {
+    /// Observations of every bucket alone, they are summed up when rendered
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Metrics of one pipeline, kept across restarts of the pipeline
+#[derive(Default)]
+pub struct PipelineMetrics {
+    pub frames_captured: Counter,
+    pub frames_sent: Counter,
+    /// Frames which were not sent because they did not change
+    pub frames_skipped: Counter,
+    pub send_errors: Counter,
+    pub reconnects: Counter,
+    /// Desktop duplication lost access to the display (mode change, UAC prompt, ...)
+    pub access_lost: Counter,
+    /// Streaming stopped because the screen stayed black or unchanged
+    pub standbys: Counter,
+    /// Acquiring a frame and copying its downscaled version from the GPU
+    pub capture_time: Histogram,
+    /// Reducing the downscaled frame into LED colors
+    pub extraction_time: Histogram,
+    /// From acquiring a frame to sending its colors
+    pub latency: Histogram,
+    pub fps: Gauge,
+    /// Rate the output actually ticks at and the rate it aims for
+    pub output_fps: Gauge,
+    pub output_target_fps: Gauge,
+    /// Brightness multiplier of the applied adjustments
+    pub brightness: Gauge,
+    /// Share of light output left by master brightness
+    pub master_brightness: Gauge,
+    /// 1 while the power limiter dims frames
+    pub power_limited: Gauge,
+}
+
+type CounterMetric = (&'static str, &'static str, fn(&PipelineMetrics) -> &Counter);
+type HistogramMetric = (
+    &'static str,
+    &'static str,
+    fn(&PipelineMetrics) -> &Histogram,
+);
+type GaugeMetric = (&'static str, &'static str, fn(&PipelineMetrics) -> &Gauge);
+
+const COUNTERS: [CounterMetric; 7] = [
+    (
+        "frames_captured_total",
+        "Frames captured from the display",
+        |m| &m.frames_captured,
+    ),
+    ("frames_sent_total", "Frames sent to WLED", |m| {
+        &m.frames_sent
+    }),
+    (
+        "frames_skipped_total",
+        "Frames which were not sent because they did not change",
+        |m| &m.frames_skipped,
+    ),
+    ("send_errors_total", "Frames which could not be sent", |m| {
+        &m.send_errors
+    }),
+    (
+        "reconnects_total",
+        "Connections to WLED created again after an error or a change of wled_ip",
+        |m| &m.reconnects,
+    ),
+    (
+        "access_lost_total",
+        "Times desktop duplication lost access to the display",
+        |m| &m.access_lost,
+    ),
+    (
+        "standbys_total",
+        "Times streaming stopped because the screen stayed black or unchanged",
+        |m| &m.standbys,
+    ),
+];
+
+const HISTOGRAMS: [HistogramMetric; 3] = [
+    (
+        "capture_seconds",
+        "Time to acquire a frame and copy it from the GPU",
+        |m| &m.capture_time,
+    ),
+    (
+        "extraction_seconds",
+        "Time to reduce a frame into LED colors",
+        |m| &m.extraction_time,
+    ),
+    (
+        "latency_seconds",
+        "Time from acquiring a frame to sending its colors",
+        |m| &m.latency,
+    ),
+];
+
+const GAUGES: [GaugeMetric; 6] = [
+    ("fps", "Frames captured per second", |m| &m.fps),
+    (
+        "output_fps",
+        "Frames per second the output actually sends or skips as unchanged",
+        |m| &m.output_fps,
+    ),
+    (
+        "output_target_fps",
+        "Frames per second the output aims for",
+        |m| &m.output_target_fps,
+    ),
+    (
+        "brightness",
+        "Brightness multiplier of the applied adjustments",
+        |m| &m.brightness,
+    ),
+    (
+        "master_brightness",
+        "Share of light output left by master brightness, applied in linear light",
+        |m| &m.master_brightness,
+    ),
+    (
+        "power_limited",
+        "1 while the power limiter dims frames to stay within the PSU budget",
+        |m| &m.power_limited,
+    ),
+];
+
+/// Metrics of all pipelines rendered in Prometheus text format
+#[derive(Default)]
+pub struct Metrics {
+    pipelines: Mutex<BTreeMap<String, Arc<PipelineMetrics>>>,
+}
+
+impl Metrics {
+    /// Metrics of pipeline `name`, created on first use
+    pub fn pipeline(&self, name: &str) -> Arc<PipelineMetrics> {
+        self.pipelines
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    pub fn render(&self) -> String {
+        let pipelines = self.pipelines.lock().unwrap();
+        let labels: Vec<(String, &PipelineMetrics)> = pipelines
+            .iter()
+            .map(|(name, metrics)| (format!("pipeline=\"{}\"", escape(name)), metrics.as_ref()))
+            .collect();
+
+        // writing into a String never fails
+        let mut out = String::new();
+        for (name, help, counter) in COUNTERS {
+            header(&mut out, name, help, "counter");
+            for (label, metrics) in &labels {
+                let _ = writeln!(
+                    out,
+                    "wled_ambilight_{}{{{}}} {}",
+                    name,
+                    label,
+                    counter(metrics).get()
+                );
+            }
+        }
+        for (name, help, gauge) in GAUGES {
+            header(&mut out, name, help, "gauge");
+            for (label, metrics) in &labels {
+                let _ = writeln!(
+                    out,
+                    "wled_ambilight_{}{{{}}} {}",
+                    name,
+                    label,
+                    gauge(metrics).get()
+                );
+            }
+        }
+        for (name, help, histogram) in HISTOGRAMS {
+            header(&mut out, name, help, "histogram");
+            for (label, metrics) in &labels {
+                let histogram = histogram(metrics);
+                let mut cumulative = 0;
+                for (bound, bucket) in BUCKETS.iter().zip(&histogram.buckets) {
+                    cumulative += bucket.load(Ordering::Relaxed);
+                    let _ = writeln!(
+                        out,
+                        "wled_ambilight_{}_bucket{{{},le=\"{}\"}} {}",
+                        name, label, bound, cumulative
+                    );
+                }
+                let count = histogram.count.load(Ordering::Relaxed);
+                let sum = histogram.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+                let _ = writeln!(
+                    out,
+                    "wled_ambilight_{}_bucket{{{},le=\"+Inf\"}} {}",
+                    name, label, count
+                );
+                let _ = writeln!(out, "wled_ambilight_{}_sum{{{}}} {}", name, label, sum);
+                let _ = writeln!(out, "wled_ambilight_{}_count{{{}}} {}", name, label, count);
+            }
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP wled_ambilight_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE wled_ambilight_{} {}", name, kind);
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines_of<'a>(out: &'a str, metric: &str) -> Vec<&'a str> {
+        out.lines()
+            .filter(|line| line.starts_with(&format!("wled_ambilight_{}", metric)))
+            .collect()
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let metrics = Metrics::default();
+        let latency = &metrics.pipeline("Main").latency;
+        latency.observe(Duration::from_micros(700));
+        latency.observe(Duration::from_millis(3));
+        // above the last bound, counted only by +Inf
+        latency.observe(Duration::from_secs(1));
+
+        let out = metrics.render();
+        let lines = lines_of(&out, "latency_seconds");
+        assert_eq!(
+            lines,
+            [
+                r#"wled_ambilight_latency_seconds_bucket{pipeline="Main",le="0.0005"} 0"#,
+                r#"wled_ambilight_latency_seconds_bucket{pipeline="Main",le="0.001"} 1"#,
+                r#"wled_ambilight_latency_seconds_bucket{pipeline="Main",le="0.002"} 1"#,
+                r#"wled_ambilight_latency_seconds_bucket{pipeline="Main",le="0.004"} 2"#,
+                r#"wled_ambilight_latency_seconds_bucket{pipeline="Main",le="0.008"} 2"#,
+                r#"wled_ambilight_latency_seconds_bucket{pipeline="Main",le="0.016"} 2"#,
+                r#"wled_ambilight_latency_seconds_bucket{pipeline="Main",le="0.033"} 2"#,
+                r#"wled_ambilight_latency_seconds_bucket{pipeline="Main",le="0.066"} 2"#,
+                r#"wled_ambilight_latency_seconds_bucket{pipeline="Main",le="0.125"} 2"#,
+                r#"wled_ambilight_latency_seconds_bucket{pipeline="Main",le="0.25"} 2"#,
+                r#"wled_ambilight_latency_seconds_bucket{pipeline="Main",le="+Inf"} 3"#,
+                r#"wled_ambilight_latency_seconds_sum{pipeline="Main"} 1.0037"#,
+                r#"wled_ambilight_latency_seconds_count{pipeline="Main"} 3"#,
+            ]
+        );
+        assert!(out.contains("# TYPE wled_ambilight_latency_seconds histogram\n"));
+    }
+
+    #[test]
+    fn counters_and_gauges_have_one_line_per_pipeline() {
+        let metrics = Metrics::default();
+        metrics.pipeline("Main").frames_sent.increment();
+        metrics.pipeline("Main").frames_sent.increment();
+        metrics.pipeline("Side").fps.set(59.5);
+
+        let out = metrics.render();
+        assert_eq!(
+            lines_of(&out, "frames_sent_total"),
+            [
+                r#"wled_ambilight_frames_sent_total{pipeline="Main"} 2"#,
+                r#"wled_ambilight_frames_sent_total{pipeline="Side"} 0"#,
+            ]
+        );
+        assert!(out.contains("# TYPE wled_ambilight_frames_sent_total counter\n"));
+        assert!(out.contains("\nwled_ambilight_fps{pipeline=\"Side\"} 59.5\n"));
+    }
+
+    #[test]
+    fn pipeline_names_are_escaped_in_labels() {
+        let metrics = Metrics::default();
+        metrics
+            .pipeline("TV \"left\"\\back\nside")
+            .standbys
+            .increment();
+
+        let out = metrics.render();
+        assert_eq!(
+            lines_of(&out, "standbys_total"),
+            [r#"wled_ambilight_standbys_total{pipeline="TV \"left\"\\back\nside"} 1"#]
+        );
+    }
+}
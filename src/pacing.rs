@@ -0,0 +1,212 @@
+use std::time::{Duration, Instant};
+
+/// How often actual FPS is recalculated
+const MEASURE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Source of time for `FramePacer`, replaced by a fake one to check pacing without waiting
+pub trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Keeps frames at a target rate. Every frame gets a deadline one period after the previous
+/// deadline, so time spent working on a frame is not added on top of the period. A frame late
+/// by more than a whole period drops the missed deadlines instead of catching up in a burst.
+pub struct FramePacer<C: Clock = SystemClock> {
+    clock: C,
+    period: Duration,
+    target_fps: f64,
+    next_deadline: Option<Instant>,
+    frame_count: u32,
+    /// Set by the first frame, which is not counted
+    measure_start: Option<Instant>,
+    actual_fps: f64,
+}
+
+impl FramePacer {
+    pub fn new(fps: u32) -> Self {
+        Self::with_clock(fps, SystemClock)
+    }
+}
+
+impl<C: Clock> FramePacer<C> {
+    pub fn with_clock(fps: u32, clock: C) -> Self {
+        let mut pacer = Self {
+            clock,
+            period: Duration::ZERO,
+            target_fps: 0.0,
+            next_deadline: None,
+            frame_count: 0,
+            measure_start: None,
+            actual_fps: 0.0,
+        };
+        pacer.set_fps(fps);
+        pacer
+    }
+
+    /// Applies from the next frame, `fps` of 0 is taken as 1
+    pub fn set_fps(&mut self, fps: u32) {
+        self.target_fps = fps.max(1) as f64;
+        self.period = Duration::from_secs_f64(1.0 / self.target_fps);
+    }
+
+    pub fn target_fps(&self) -> f64 {
+        self.target_fps
+    }
+
+    /// Frames per second which went through `wait` during the last measurement period
+    pub fn actual_fps(&self) -> f64 {
+        self.actual_fps
+    }
+
+    /// Sleeps until the next frame is due, the first frame is due right away
+    pub fn wait(&mut self) {
+        let now = self.clock.now();
+        let deadline = match self.next_deadline {
+            Some(deadline) if now < deadline => {
+                self.clock.sleep(deadline - now);
+                deadline
+            }
+            // late by less than a period, the next deadline stays in step
+            Some(deadline) if now - deadline < self.period => deadline,
+            _ => now,
+        };
+        self.next_deadline = Some(deadline + self.period);
+        self.measure(deadline);
+    }
+
+    fn measure(&mut self, now: Instant) {
+        let Some(measure_start) = self.measure_start else {
+            self.measure_start = Some(now);
+            return;
+        };
+        self.frame_count += 1;
+        let elapsed = now.saturating_duration_since(measure_start);
+        if elapsed >= MEASURE_PERIOD {
+            self.actual_fps = self.frame_count as f64 / elapsed.as_secs_f64();
+            self.frame_count = 0;
+            self.measure_start = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    /// Time moves only when the pacer sleeps or a test works on a frame
+    #[derive(Clone)]
+    struct ManualClock(Rc<Cell<Instant>>);
+
+    impl ManualClock {
+        fn new() -> Self {
+            Self(Rc::new(Cell::new(Instant::now())))
+        }
+
+        fn advance(&self, duration: Duration) {
+            self.0.set(self.0.get() + duration);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            self.0.get()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.advance(duration);
+        }
+    }
+
+    const PERIOD: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn on_time_frames_advance_deadline_by_period() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut pacer = FramePacer::with_clock(100, clock.clone());
+
+        pacer.wait();
+        assert_eq!(clock.now(), start, "first frame is due right away");
+        for frame in 1..=5 {
+            clock.advance(Duration::from_millis(3));
+            pacer.wait();
+            assert_eq!(clock.now(), start + PERIOD * frame);
+        }
+    }
+
+    #[test]
+    fn small_overrun_keeps_cadence() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut pacer = FramePacer::with_clock(100, clock.clone());
+
+        pacer.wait();
+        clock.advance(Duration::from_millis(14));
+        pacer.wait();
+        assert_eq!(
+            clock.now(),
+            start + Duration::from_millis(14),
+            "late frame goes right away"
+        );
+        pacer.wait();
+        assert_eq!(clock.now(), start + PERIOD * 2, "next one is back in step");
+    }
+
+    #[test]
+    fn large_overrun_restarts_from_now() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut pacer = FramePacer::with_clock(100, clock.clone());
+
+        pacer.wait();
+        clock.advance(Duration::from_millis(35));
+        pacer.wait();
+        assert_eq!(clock.now(), start + Duration::from_millis(35));
+        pacer.wait();
+        assert_eq!(
+            clock.now(),
+            start + Duration::from_millis(45),
+            "missed deadlines are dropped, not caught up"
+        );
+    }
+
+    #[test]
+    fn actual_fps_is_measured_over_a_period() {
+        let clock = ManualClock::new();
+        let mut pacer = FramePacer::with_clock(50, clock.clone());
+        assert_eq!(pacer.actual_fps(), 0.0);
+
+        for _ in 0..=50 {
+            pacer.wait();
+        }
+        assert_eq!(pacer.actual_fps(), 50.0);
+
+        // every frame takes twice its period
+        for _ in 0..25 {
+            clock.advance(Duration::from_millis(40));
+            pacer.wait();
+        }
+        assert_eq!(pacer.actual_fps(), 25.0);
+    }
+
+    #[test]
+    fn zero_fps_is_taken_as_one() {
+        let pacer = FramePacer::with_clock(0, ManualClock::new());
+        assert_eq!(pacer.target_fps(), 1.0);
+    }
+}
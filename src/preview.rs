@@ -0,0 +1,121 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread::sleep,
+    time::Duration,
+};
+
+use log::info;
+use serde::Serialize;
+use tiny_http::{Header, Request, Response};
+use tungstenite::{handshake::derive_accept_key, protocol::Role, Message, WebSocket};
+
+use crate::config::PipelineConfig;
+
+/// Browser page drawing the strip, served by the HTTP API on `/preview`
+pub const PAGE: &str = include_str!("preview.html");
+
+/// Colors are streamed at this rate no matter how fast pipelines run
+const PREVIEW_PERIOD: Duration = Duration::from_millis(66);
+
+/// One pipeline in the same layout as `BorderColors`, clockwise from the top left corner:
+/// top row left to right, right column, bottom row right to left, left column. Horizontal rows
+/// include the corners, vertical columns do not.
+#[derive(Serialize, Debug, Clone)]
+struct PreviewFrame {
+    name: String,
+    led_horizontal_count: u32,
+    led_vertical_count: u32,
+    /// `rrggbb` of every LED, white channel is added to the others
+    colors: String,
+}
+
+#[derive(Serialize)]
+struct PreviewMessage<'a> {
+    pipelines: Vec<&'a PreviewFrame>,
+}
+
+/// Latest outgoing colors of every pipeline, pipelines copy them only while somebody watches
+#[derive(Default)]
+pub struct Preview {
+    viewers: AtomicUsize,
+    frames: Mutex<BTreeMap<String, PreviewFrame>>,
+}
+
+impl Preview {
+    pub fn is_watched(&self) -> bool {
+        self.viewers.load(Ordering::Relaxed) > 0
+    }
+
+    /// Stores `colors` (RGBW bytes of every LED) which were just sent by `pipeline`
+    pub fn publish(&self, pipeline: &PipelineConfig, colors: &[u8]) {
+        let mut hex = String::with_capacity(colors.len() / 4 * 6);
+        for color in colors.chunks_exact(4) {
+            for channel in &color[..3] {
+                // writing into a String never fails
+                let _ = write!(hex, "{:02x}", channel.saturating_add(color[3]));
+            }
+        }
+
+        self.frames.lock().unwrap().insert(
+            pipeline.name.clone(),
+            PreviewFrame {
+                name: pipeline.name.clone(),
+                led_horizontal_count: pipeline.led_horizontal_count,
+                led_vertical_count: pipeline.led_vertical_count,
+                colors: hex,
+            },
+        );
+    }
+
+    /// Forgets colors of a pipeline which stopped
+    pub fn clear(&self, name: &str) {
+        self.frames.lock().unwrap().remove(name);
+    }
+
+    /// Upgrades `request` to a WebSocket and streams colors into it until the client leaves
+    /// or `is_running` is cleared, blocks the calling thread
+    pub fn stream(&self, request: Request, is_running: &AtomicBool) {
+        let Some(key) = request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv("Sec-WebSocket-Key"))
+            .map(|header| derive_accept_key(header.value.as_bytes()))
+        else {
+            let _ = request.respond(
+                Response::from_string("expected a WebSocket request").with_status_code(400),
+            );
+            return;
+        };
+
+        let response = Response::empty(101)
+            .with_header(Header::from_bytes("Sec-WebSocket-Accept", key).expect("header is valid"));
+        let stream = request.upgrade("websocket", response);
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+
+        info!("Preview client connected");
+        self.viewers.fetch_add(1, Ordering::Relaxed);
+
+        while is_running.load(Ordering::Relaxed) {
+            let message = {
+                let frames = self.frames.lock().unwrap();
+                serde_json::to_string(&PreviewMessage {
+                    pipelines: frames.values().collect(),
+                })
+                .expect("preview is always serializable")
+            };
+            if socket.write_message(Message::Text(message)).is_err() {
+                break;
+            }
+            sleep(PREVIEW_PERIOD);
+        }
+
+        self.viewers.fetch_sub(1, Ordering::Relaxed);
+        let _ = socket.close(None);
+        info!("Preview client disconnected");
+    }
+}
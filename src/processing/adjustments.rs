@@ -0,0 +1,140 @@
+use crate::config::{ColorAdjustments, SaturationMode};
+
+/// Applies contrast, saturation, brightness and minimum brightness to one RGB color
+pub fn adjust_color(rgb: &mut [u8], adjustments: &ColorAdjustments) {
+    let mut color = [
+        rgb[0] as f32 / 255.0,
+        rgb[1] as f32 / 255.0,
+        rgb[2] as f32 / 255.0,
+    ];
+
+    if adjustments.contrast != 1.0 {
+        color
+            .iter_mut()
+            .for_each(|c| *c = (*c - 0.5) * adjustments.contrast as f32 + 0.5);
+    }
+
+    if adjustments.saturation != 1.0 {
+        color = match adjustments.saturation_mode {
+            SaturationMode::Hsv => saturate_hsv(color, adjustments.saturation as f32),
+            SaturationMode::Oklab => saturate_oklab(color, adjustments.saturation as f32),
+        };
+    }
+
+    if adjustments.brightness != 1.0 {
+        color
+            .iter_mut()
+            .for_each(|c| *c *= adjustments.brightness as f32);
+    }
+
+    for (channel, value) in rgb.iter_mut().zip(color) {
+        *channel = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    }
+
+    apply_min_brightness(rgb, adjustments.min_brightness);
+}
+
+/// Raises the brightest channel to `floor` while keeping the hue
+fn apply_min_brightness(rgb: &mut [u8], floor: u8) {
+    let value = rgb[0].max(rgb[1]).max(rgb[2]);
+    if value >= floor {
+        return;
+    }
+
+    if value == 0 {
+        rgb[..3].fill(floor);
+        return;
+    }
+
+    let scale = floor as f32 / value as f32;
+    rgb[..3]
+        .iter_mut()
+        .for_each(|c| *c = (*c as f32 * scale).round().min(255.0) as u8);
+}
+
+fn saturate_hsv([r, g, b]: [f32; 3], saturation: f32) -> [f32; 3] {
+    let r = r.clamp(0.0, 1.0);
+    let g = g.clamp(0.0, 1.0);
+    let b = b.clamp(0.0, 1.0);
+
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    if max <= 0.0 || delta <= 0.0 {
+        return [r, g, b];
+    }
+
+    let hue = if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    let s = (delta / max * saturation).clamp(0.0, 1.0);
+
+    let chroma = max * s;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let m = max - chroma;
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    [r + m, g + m, b + m]
+}
+
+fn saturate_oklab(rgb: [f32; 3], saturation: f32) -> [f32; 3] {
+    let [l, a, b] = linear_to_oklab(rgb.map(|c| srgb_to_linear(c.clamp(0.0, 1.0))));
+    oklab_to_linear([l, a * saturation, b * saturation]).map(linear_to_srgb)
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn linear_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = (0.41222147 * r + 0.53633254 * g + 0.05144599 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+    let s = (0.08830246 * r + 0.28171884 * g + 0.6299787 * b).cbrt();
+
+    [
+        0.21045426 * l + 0.7936178 * m - 0.004072047 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.025904037 * l + 0.78277177 * m - 0.80867577 * s,
+    ]
+}
+
+fn oklab_to_linear([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let l_ = (l + 0.39633778 * a + 0.21580376 * b).powi(3);
+    let m_ = (l - 0.105561346 * a - 0.06385417 * b).powi(3);
+    let s_ = (l - 0.08948418 * a - 1.2914855 * b).powi(3);
+
+    [
+        4.0767417 * l_ - 3.3077116 * m_ + 0.23096994 * s_,
+        -1.268438 * l_ + 2.6097574 * m_ - 0.34131938 * s_,
+        -0.0041960864 * l_ - 0.7034186 * m_ + 1.7076147 * s_,
+    ]
+}
+
+/// Rec. 709 luma of one RGB color in range 0.0-1.0
+pub fn luma(rgb: &[u8]) -> f32 {
+    (0.2126 * rgb[0] as f32 + 0.7152 * rgb[1] as f32 + 0.0722 * rgb[2] as f32) / 255.0
+}
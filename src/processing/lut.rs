@@ -0,0 +1,432 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::config::LutInterpolation;
+
+#[derive(Debug)]
+pub enum LutError {
+    Io(io::Error),
+    /// `line` is 1-based, 0 means the problem is not tied to a single line
+    Parse {
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for LutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LutError::Io(err) => write!(f, "could not read LUT file: {}", err),
+            LutError::Parse { line: 0, message } => write!(f, "{}", message),
+            LutError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for LutError {}
+
+fn parse_error(line: usize, message: impl Into<String>) -> LutError {
+    LutError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+/// Table loaded by `Lut3d::load_shared` with modification time of its file
+type LoadedLut = (Option<SystemTime>, Arc<Lut3d>);
+
+static LOADED: Mutex<BTreeMap<PathBuf, LoadedLut>> = Mutex::new(BTreeMap::new());
+
+/// 3D color lookup table loaded from an Adobe/Resolve `.cube` file
+pub struct Lut3d {
+    size: usize,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    /// Red changes fastest, then green, then blue
+    table: Vec<[f32; 3]>,
+}
+
+impl Lut3d {
+    pub fn load(path: &Path) -> Result<Self, LutError> {
+        let content = fs::read_to_string(path).map_err(LutError::Io)?;
+        Self::parse(&content)
+    }
+
+    /// Same as `load`, but the file is parsed again only when it changed since the last call,
+    /// validation of every reload and every pipeline update share the loaded table
+    pub fn load_shared(path: impl AsRef<Path>) -> Result<Arc<Self>, LutError> {
+        let path = path.as_ref();
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        if let Some((loaded_modified, lut)) = LOADED.lock().unwrap().get(path) {
+            if modified.is_some() && *loaded_modified == modified {
+                return Ok(lut.clone());
+            }
+        }
+
+        let lut = Arc::new(Self::load(path)?);
+        LOADED
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), (modified, lut.clone()));
+        Ok(lut)
+    }
+
+    /// Files of tables kept by `load_shared`
+    pub fn shared_paths() -> Vec<PathBuf> {
+        LOADED.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Drops shared tables of every file not in `paths`, so tables of removed pipelines and
+    /// profiles don't stay loaded and their files are not watched anymore
+    pub fn retain_shared(paths: &[PathBuf]) {
+        LOADED
+            .lock()
+            .unwrap()
+            .retain(|path, _| paths.contains(path));
+    }
+
+    pub fn parse(content: &str) -> Result<Self, LutError> {
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut table = Vec::new();
+
+        for (index, line) in content.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let keyword = parts.next().unwrap_or_default();
+            match keyword {
+                "TITLE" => {}
+                "LUT_3D_SIZE" => {
+                    if !table.is_empty() {
+                        return Err(parse_error(line_number, "LUT_3D_SIZE after table data"));
+                    }
+                    let value = parts
+                        .next()
+                        .and_then(|v| v.parse::<usize>().ok())
+                        .filter(|v| (2..=256).contains(v))
+                        .ok_or_else(|| {
+                            parse_error(line_number, "LUT_3D_SIZE has to be a number in 2-256")
+                        })?;
+                    size = Some(value);
+                    table.reserve(value * value * value);
+                }
+                "LUT_1D_SIZE" => {
+                    return Err(parse_error(line_number, "1D LUTs are not supported"));
+                }
+                "DOMAIN_MIN" => domain_min = parse_triplet(parts, line_number)?,
+                "DOMAIN_MAX" => domain_max = parse_triplet(parts, line_number)?,
+                "LUT_3D_INPUT_RANGE" => {
+                    let min = parse_float(parts.next(), line_number)?;
+                    let max = parse_float(parts.next(), line_number)?;
+                    domain_min = [min; 3];
+                    domain_max = [max; 3];
+                }
+                _ if keyword.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') => {
+                    let Some(size) = size else {
+                        return Err(parse_error(line_number, "table data before LUT_3D_SIZE"));
+                    };
+                    if table.len() == size * size * size {
+                        return Err(parse_error(
+                            line_number,
+                            format!("more than {} table entries", size * size * size),
+                        ));
+                    }
+                    table.push(parse_triplet(line.split_whitespace(), line_number)?);
+                }
+                _ => {
+                    return Err(parse_error(
+                        line_number,
+                        format!("unknown keyword `{}`", keyword),
+                    ));
+                }
+            }
+        }
+
+        let Some(size) = size else {
+            return Err(parse_error(0, "missing LUT_3D_SIZE"));
+        };
+        if table.len() != size * size * size {
+            return Err(parse_error(
+                0,
+                format!(
+                    "expected {} table entries for size {}, found {}",
+                    size * size * size,
+                    size,
+                    table.len()
+                ),
+            ));
+        }
+        if (0..3).any(|i| domain_max[i] <= domain_min[i]) {
+            return Err(parse_error(0, "DOMAIN_MAX has to be above DOMAIN_MIN"));
+        }
+
+        Ok(Self {
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    /// Maps one RGB color through the table
+    pub fn apply(&self, rgb: &mut [u8], interpolation: LutInterpolation) {
+        let max_index = (self.size - 1) as f32;
+        let mut position = [0.0; 3];
+        for i in 0..3 {
+            let value = rgb[i] as f32 / 255.0;
+            let normalized =
+                (value - self.domain_min[i]) / (self.domain_max[i] - self.domain_min[i]);
+            position[i] = normalized.clamp(0.0, 1.0) * max_index;
+        }
+
+        let color = match interpolation {
+            LutInterpolation::Trilinear => self.trilinear(position),
+            LutInterpolation::Tetrahedral => self.tetrahedral(position),
+        };
+
+        for (channel, value) in rgb.iter_mut().zip(color) {
+            *channel = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+    }
+
+    fn entry(&self, r: usize, g: usize, b: usize) -> [f32; 3] {
+        self.table[r + g * self.size + b * self.size * self.size]
+    }
+
+    /// Splits grid position into lower corner indices and fractions towards the upper corner
+    fn cell(&self, position: [f32; 3]) -> ([usize; 3], [usize; 3], [f32; 3]) {
+        let mut low = [0; 3];
+        let mut high = [0; 3];
+        let mut fraction = [0.0; 3];
+        for i in 0..3 {
+            low[i] = (position[i].floor() as usize).min(self.size - 2);
+            high[i] = low[i] + 1;
+            fraction[i] = position[i] - low[i] as f32;
+        }
+        (low, high, fraction)
+    }
+
+    fn trilinear(&self, position: [f32; 3]) -> [f32; 3] {
+        let ([r0, g0, b0], [r1, g1, b1], [fr, fg, fb]) = self.cell(position);
+
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| -> [f32; 3] {
+            [
+                a[0] + (b[0] - a[0]) * t,
+                a[1] + (b[1] - a[1]) * t,
+                a[2] + (b[2] - a[2]) * t,
+            ]
+        };
+
+        let c00 = lerp(self.entry(r0, g0, b0), self.entry(r1, g0, b0), fr);
+        let c10 = lerp(self.entry(r0, g1, b0), self.entry(r1, g1, b0), fr);
+        let c01 = lerp(self.entry(r0, g0, b1), self.entry(r1, g0, b1), fr);
+        let c11 = lerp(self.entry(r0, g1, b1), self.entry(r1, g1, b1), fr);
+
+        lerp(lerp(c00, c10, fg), lerp(c01, c11, fg), fb)
+    }
+
+    fn tetrahedral(&self, position: [f32; 3]) -> [f32; 3] {
+        let ([r0, g0, b0], [r1, g1, b1], [fr, fg, fb]) = self.cell(position);
+
+        let c000 = self.entry(r0, g0, b0);
+        let c111 = self.entry(r1, g1, b1);
+
+        // pick the tetrahedron containing the point, walk from c000 to c111 along the largest fractions
+        let (t1, t2, t3, c_first, c_second) = if fr > fg {
+            if fg > fb {
+                (fr, fg, fb, self.entry(r1, g0, b0), self.entry(r1, g1, b0))
+            } else if fr > fb {
+                (fr, fb, fg, self.entry(r1, g0, b0), self.entry(r1, g0, b1))
+            } else {
+                (fb, fr, fg, self.entry(r0, g0, b1), self.entry(r1, g0, b1))
+            }
+        } else if fb > fg {
+            (fb, fg, fr, self.entry(r0, g0, b1), self.entry(r0, g1, b1))
+        } else if fb > fr {
+            (fg, fb, fr, self.entry(r0, g1, b0), self.entry(r0, g1, b1))
+        } else {
+            (fg, fr, fb, self.entry(r0, g1, b0), self.entry(r1, g1, b0))
+        };
+
+        let mut color = [0.0; 3];
+        for i in 0..3 {
+            color[i] = (1.0 - t1) * c000[i]
+                + (t1 - t2) * c_first[i]
+                + (t2 - t3) * c_second[i]
+                + t3 * c111[i];
+        }
+        color
+    }
+}
+
+fn parse_float(value: Option<&str>, line: usize) -> Result<f32, LutError> {
+    let value = value.ok_or_else(|| parse_error(line, "expected 3 numbers"))?;
+    value
+        .parse::<f32>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or_else(|| parse_error(line, format!("`{}` is not a number", value)))
+}
+
+fn parse_triplet<'a>(
+    mut parts: impl Iterator<Item = &'a str>,
+    line: usize,
+) -> Result<[f32; 3], LutError> {
+    let triplet = [
+        parse_float(parts.next(), line)?,
+        parse_float(parts.next(), line)?,
+        parse_float(parts.next(), line)?,
+    ];
+    if parts.next().is_some() {
+        return Err(parse_error(line, "expected 3 numbers"));
+    }
+    Ok(triplet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Table of `size` mapping every color onto itself
+    fn identity(size: usize) -> String {
+        let mut content = format!("TITLE \"identity\"\nLUT_3D_SIZE {}\n", size);
+        let max = (size - 1) as f32;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    content.push_str(&format!(
+                        "{} {} {}\n",
+                        r as f32 / max,
+                        g as f32 / max,
+                        b as f32 / max
+                    ));
+                }
+            }
+        }
+        content
+    }
+
+    fn parse_error_line(content: &str) -> (usize, String) {
+        match Lut3d::parse(content) {
+            Err(LutError::Parse { line, message }) => (line, message),
+            Err(err) => panic!("expected parse error, got {}", err),
+            Ok(_) => panic!("expected parse error, LUT was parsed"),
+        }
+    }
+
+    #[test]
+    fn reports_line_of_invalid_number() {
+        let content = "# comment\nLUT_3D_SIZE 2\n0 0 0\n1 x 0\n";
+        let (line, message) = parse_error_line(content);
+        assert_eq!(line, 4);
+        assert_eq!(message, "`x` is not a number");
+    }
+
+    #[test]
+    fn reports_line_of_data_before_size() {
+        let (line, message) = parse_error_line("TITLE \"lut\"\n\n0 0 0\n");
+        assert_eq!(line, 3);
+        assert_eq!(message, "table data before LUT_3D_SIZE");
+    }
+
+    #[test]
+    fn reports_line_of_unknown_keyword() {
+        let (line, _) = parse_error_line("LUT_3D_SIZE 2\nGAMMA 2.2\n");
+        assert_eq!(line, 2);
+    }
+
+    #[test]
+    fn reports_missing_entries_without_line() {
+        let (line, message) = parse_error_line("LUT_3D_SIZE 2\n0 0 0\n");
+        assert_eq!(line, 0);
+        assert_eq!(message, "expected 8 table entries for size 2, found 1");
+        assert_eq!(
+            Lut3d::parse("LUT_3D_SIZE 2\n0 0 0\n")
+                .err()
+                .unwrap()
+                .to_string(),
+            message
+        );
+    }
+
+    #[test]
+    fn error_message_contains_line() {
+        let err = Lut3d::parse("LUT_3D_SIZE 1\n").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "line 1: LUT_3D_SIZE has to be a number in 2-256"
+        );
+    }
+
+    #[test]
+    fn identity_keeps_colors() {
+        for size in [2, 5, 17] {
+            let lut = Lut3d::parse(&identity(size)).unwrap();
+            for interpolation in [LutInterpolation::Trilinear, LutInterpolation::Tetrahedral] {
+                for color in [[0, 0, 0], [255, 255, 255], [12, 200, 99], [255, 1, 128]] {
+                    let mut rgb = color;
+                    lut.apply(&mut rgb, interpolation);
+                    assert_eq!(rgb, color, "size {} {:?}", size, interpolation);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tetrahedral_interpolates_inside_cell() {
+        // 2x2x2 table inverting red only, every tetrahedron has to agree on a linear mapping
+        let content = "LUT_3D_SIZE 2\n\
+                       1 0 0\n0 0 0\n1 1 0\n0 1 0\n\
+                       1 0 1\n0 0 1\n1 1 1\n0 1 1\n";
+        let lut = Lut3d::parse(content).unwrap();
+        for color in [
+            [51, 102, 204],
+            [204, 102, 51],
+            [102, 204, 51],
+            [128, 128, 128],
+        ] {
+            let mut rgb = color;
+            lut.apply(&mut rgb, LutInterpolation::Tetrahedral);
+            assert_eq!(rgb, [255 - color[0], color[1], color[2]]);
+        }
+    }
+
+    #[test]
+    fn shared_table_is_loaded_again_after_edit_and_dropped_when_unused() {
+        let dir = std::env::temp_dir().join(format!("wled_ambilight_lut_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let used = dir.join("used.cube");
+        let unused = dir.join("unused.cube");
+        fs::write(&used, identity(2)).unwrap();
+        fs::write(&unused, identity(2)).unwrap();
+
+        let first = Lut3d::load_shared(&used).unwrap();
+        assert!(Arc::ptr_eq(&first, &Lut3d::load_shared(&used).unwrap()));
+        Lut3d::load_shared(&unused).unwrap();
+
+        fs::write(&used, identity(3)).unwrap();
+        let file = fs::File::options().write(true).open(&used).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(5))
+            .unwrap();
+        let edited = Lut3d::load_shared(&used).unwrap();
+        assert_eq!(edited.size, 3);
+
+        Lut3d::retain_shared(std::slice::from_ref(&used));
+        let shared = Lut3d::shared_paths();
+        assert!(shared.contains(&used));
+        assert!(!shared.contains(&unused));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+mod adjustments;
+mod lut;
+mod power_limiter;
+
+use log::warn;
+use std::sync::Arc;
+
+use crate::config::{ColorAdjustments, DarkSceneConfig, LutInterpolation, PipelineConfig};
+
+use self::adjustments::{adjust_color, linear_to_srgb, luma, srgb_to_linear};
+pub use self::lut::Lut3d;
+use self::power_limiter::PowerLimiter;
+
+/// Luma has to rise this much above the threshold before leaving the dark scene profile,
+/// so scenes hovering around the threshold don't flicker between profiles
+const DARK_SCENE_HYSTERESIS: f32 = 1.25;
+
+/// Post-processing of extracted LED colors, runs right before they are encoded into a packet
+pub struct ColorProcessor {
+    adjustments: ColorAdjustments,
+    dark_scene: Option<DarkSceneConfig>,
+    is_dark_scene: bool,
+    lut: Option<(Arc<Lut3d>, LutInterpolation)>,
+    power_limiter: Option<PowerLimiter>,
+}
+
+impl ColorProcessor {
+    pub fn new(config: &PipelineConfig) -> Self {
+        let lut = config
+            .lut
+            .as_ref()
+            .and_then(|lut| match Lut3d::load_shared(&lut.path) {
+                Ok(table) => Some((table, lut.interpolation)),
+                Err(err) => {
+                    warn!(
+                        pipeline = config.name;
+                        "Could not load LUT {}, continuing without it: {}",
+                        lut.path, err
+                    );
+                    None
+                }
+            });
+
+        let layout_led_count =
+            ((config.led_horizontal_count + config.led_vertical_count) * 2) as usize;
+        let power_limiter = config.power_limiter.clone().map(|limiter| {
+            PowerLimiter::new(&config.name, limiter, config.wled_type, layout_led_count)
+        });
+
+        Self {
+            adjustments: config.adjustments.clone(),
+            dark_scene: config.dark_scene.clone(),
+            is_dark_scene: false,
+            lut,
+            power_limiter,
+        }
+    }
+
+    /// Processes LED colors in place, `colors` holds 4 bytes (RGBW) per LED
+    pub fn process(&mut self, colors: &mut [u8]) {
+        self.adjust(colors);
+        self.limit(colors);
+    }
+
+    /// Every stage but the power limiter, colors mixed into the result later still have to
+    /// go through `limit`
+    pub fn adjust(&mut self, colors: &mut [u8]) {
+        if let Some(dark_scene) = &self.dark_scene {
+            self.is_dark_scene =
+                is_dark_scene(colors, dark_scene.luma_threshold as f32, self.is_dark_scene);
+        }
+
+        let adjustments = match &self.dark_scene {
+            Some(dark_scene) if self.is_dark_scene => &dark_scene.adjustments,
+            _ => &self.adjustments,
+        };
+
+        if *adjustments != ColorAdjustments::default() {
+            colors
+                .chunks_exact_mut(4)
+                .for_each(|led| adjust_color(&mut led[..3], adjustments));
+        }
+
+        if let Some((lut, interpolation)) = &self.lut {
+            colors
+                .chunks_exact_mut(4)
+                .for_each(|led| lut.apply(&mut led[..3], *interpolation));
+        }
+    }
+
+    /// Whether the power limiter dimmed the last frame
+    pub fn is_power_limited(&self) -> bool {
+        self.power_limiter
+            .as_ref()
+            .is_some_and(PowerLimiter::is_limiting)
+    }
+
+    /// Power limiter, has to see the final colors since it limits what is actually sent to
+    /// the strip
+    pub fn limit(&mut self, colors: &mut [u8]) {
+        if let Some(power_limiter) = &mut self.power_limiter {
+            power_limiter.process(colors);
+        }
+    }
+}
+
+/// Scales light output of every channel (RGBW) by master brightness in linear light, scaling
+/// the gamma encoded values would shift hues of dim colors. The table is built once per
+/// brightness since it runs on every frame.
+pub struct MasterBrightness {
+    brightness: f64,
+    /// `None` at full brightness, nothing has to be done then
+    table: Option<[u8; 256]>,
+}
+
+impl MasterBrightness {
+    pub fn new(brightness: f64) -> Self {
+        let table = (brightness < 1.0).then(|| {
+            let scale = brightness.max(0.0) as f32;
+            let mut table = [0; 256];
+            for (value, entry) in table.iter_mut().enumerate() {
+                let linear = srgb_to_linear(value as f32 / 255.0) * scale;
+                *entry = (linear_to_srgb(linear) * 255.0).round() as u8;
+            }
+            table
+        });
+        Self { brightness, table }
+    }
+
+    pub fn brightness(&self) -> f64 {
+        self.brightness
+    }
+
+    pub fn apply(&self, colors: &mut [u8]) {
+        if let Some(table) = &self.table {
+            colors
+                .iter_mut()
+                .for_each(|channel| *channel = table[*channel as usize]);
+        }
+    }
+}
+
+fn is_dark_scene(colors: &[u8], threshold: f32, was_dark_scene: bool) -> bool {
+    let led_count = colors.len() / 4;
+    if led_count == 0 {
+        return was_dark_scene;
+    }
+
+    let average_luma = colors.chunks_exact(4).map(luma).sum::<f32>() / led_count as f32;
+    if was_dark_scene {
+        average_luma < threshold * DARK_SCENE_HYSTERESIS
+    } else {
+        average_luma < threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn master_brightness_scales_linear_light() {
+        let half = MasterBrightness::new(0.5);
+        let mut colors = [255, 255, 255, 0, 128, 64, 0, 255];
+        half.apply(&mut colors);
+        // half the light of full white is far brighter than half of its encoded value
+        assert_eq!(colors[..4], [188, 188, 188, 0]);
+        // every channel gets the same share of light, so the hue is kept
+        let linear = |value: u8| srgb_to_linear(value as f32 / 255.0);
+        assert!((linear(colors[4]) / linear(colors[5]) - linear(128) / linear(64)).abs() < 0.05);
+        assert_eq!(colors[6], 0);
+        assert_eq!(colors[7], 188);
+    }
+
+    #[test]
+    fn full_and_no_master_brightness() {
+        let mut colors: Vec<u8> = (0..=255).collect();
+        MasterBrightness::new(1.0).apply(&mut colors);
+        assert!(colors.iter().enumerate().all(|(i, &c)| c as usize == i));
+        MasterBrightness::new(0.0).apply(&mut colors);
+        assert!(colors.iter().all(|&c| c == 0));
+    }
+}
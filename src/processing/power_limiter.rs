@@ -0,0 +1,181 @@
+use log::{info, warn};
+
+use crate::config::{PowerLimiterConfig, WledType};
+
+/// Scales whole frames down so the estimated current of the strip stays under the PSU budget
+pub struct PowerLimiter {
+    name: String,
+    config: PowerLimiterConfig,
+    wled_type: WledType,
+    led_count: usize,
+    scale: f32,
+}
+
+impl PowerLimiter {
+    pub fn new(
+        name: &str,
+        config: PowerLimiterConfig,
+        wled_type: WledType,
+        layout_led_count: usize,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            wled_type,
+            led_count: config
+                .led_count
+                .map(|count| count as usize)
+                .unwrap_or(layout_led_count),
+            config,
+            scale: 1.0,
+        }
+    }
+
+    /// Whether the last processed frame had to be dimmed
+    pub fn is_limiting(&self) -> bool {
+        self.scale < 1.0
+    }
+
+    pub fn process(&mut self, colors: &mut [u8]) {
+        let channel_sum = self.sent_channel_sum(colors);
+        let color_milliamps = self.channel_milliamps(channel_sum);
+        let available_milliamps =
+            (self.config.psu_budget_milliamps as f32 - self.idle_milliamps()).max(0.0);
+
+        let scale = if color_milliamps > available_milliamps {
+            available_milliamps / color_milliamps
+        } else {
+            1.0
+        };
+
+        if scale < 1.0 {
+            // white of an RGB strip is sent within the other channels, where it may clip,
+            // scaling the colors as sent keeps the estimate exact
+            if self.wled_type == WledType::Rgb {
+                fold_white(colors);
+            }
+            colors
+                .iter_mut()
+                .for_each(|c| *c = (*c as f32 * scale).floor() as u8);
+        }
+
+        if (scale < 1.0) != self.is_limiting() {
+            if scale < 1.0 {
+                warn!(
+                    pipeline = self.name;
+                    "Power limiter active, frame needs {:.0} of {:.0} mA, dimming to {:.0}%",
+                    self.idle_milliamps() + color_milliamps,
+                    self.config.psu_budget_milliamps,
+                    scale * 100.0
+                );
+            } else {
+                info!(pipeline = self.name; "Power limiter released");
+            }
+        }
+        self.scale = scale;
+    }
+
+    /// Sum of the channels as they are sent, an RGB strip gets white added to each channel
+    fn sent_channel_sum(&self, colors: &[u8]) -> u64 {
+        match self.wled_type {
+            WledType::Rgbw => colors.iter().map(|&c| c as u64).sum(),
+            WledType::Rgb => colors
+                .chunks_exact(4)
+                .flat_map(|led| led[..3].iter().map(|c| c.saturating_add(led[3]) as u64))
+                .sum(),
+        }
+    }
+
+    fn idle_milliamps(&self) -> f32 {
+        self.config.idle_milliamps_per_led as f32 * self.led_count as f32
+    }
+
+    fn channel_milliamps(&self, channel_sum: u64) -> f32 {
+        channel_sum as f32 / 255.0 * self.config.milliamps_per_channel as f32
+    }
+}
+
+/// Moves white into the other channels the way it is sent to an RGB strip
+fn fold_white(colors: &mut [u8]) {
+    for led in colors.chunks_exact_mut(4) {
+        let white = led[3];
+        led[..3]
+            .iter_mut()
+            .for_each(|channel| *channel = channel.saturating_add(white));
+        led[3] = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 10 mA per fully lit channel, layout of 2 LEDs
+    fn limiter(wled_type: WledType, psu_budget_milliamps: f64, idle: f64) -> PowerLimiter {
+        let config = PowerLimiterConfig {
+            milliamps_per_channel: 10.0,
+            idle_milliamps_per_led: idle,
+            psu_budget_milliamps,
+            led_count: None,
+        };
+        PowerLimiter::new("test", config, wled_type, 2)
+    }
+
+    fn sent_milliamps(limiter: &PowerLimiter, colors: &[u8]) -> f32 {
+        limiter.channel_milliamps(limiter.sent_channel_sum(colors)) + limiter.idle_milliamps()
+    }
+
+    #[test]
+    fn frame_within_budget_is_untouched() {
+        let mut limiter = limiter(WledType::Rgbw, 80.0, 0.0);
+        let mut colors = [255, 255, 255, 255, 255, 255, 255, 255];
+        limiter.process(&mut colors);
+        assert_eq!(colors, [255; 8]);
+        assert!(!limiter.is_limiting());
+    }
+
+    #[test]
+    fn frame_over_budget_is_dimmed_to_budget_and_released_again() {
+        let mut limiter = limiter(WledType::Rgbw, 40.0, 2.0);
+        let mut colors = [255; 8];
+        limiter.process(&mut colors);
+        assert!(limiter.is_limiting());
+        // 36 mA are left after idle current of both LEDs, out of 80 mA needed
+        assert_eq!(colors, [114; 8]);
+        assert!(sent_milliamps(&limiter, &colors) <= 40.0);
+
+        let mut dark = [10; 8];
+        limiter.process(&mut dark);
+        assert_eq!(dark, [10; 8]);
+        assert!(!limiter.is_limiting());
+    }
+
+    #[test]
+    fn white_of_rgb_strip_counts_in_every_channel() {
+        let white = [0, 0, 0, 255, 0, 0, 0, 255];
+
+        let mut rgbw = limiter(WledType::Rgbw, 20.0, 0.0);
+        let mut colors = white;
+        rgbw.process(&mut colors);
+        assert_eq!(colors, white);
+
+        // sent as 255, 255, 255 per LED, three times the current of the white channel
+        let mut rgb = limiter(WledType::Rgb, 20.0, 0.0);
+        let mut colors = white;
+        rgb.process(&mut colors);
+        assert!(rgb.is_limiting());
+        assert!(sent_milliamps(&rgb, &colors) <= 20.0);
+    }
+
+    #[test]
+    fn clipped_white_of_rgb_strip_is_dimmed_as_sent() {
+        // red and white add up past 255, the strip gets 255 no matter how high they are
+        let mut limiter = limiter(WledType::Rgb, 30.0, 0.0);
+        let mut colors = [200, 100, 0, 200, 200, 100, 0, 200];
+        limiter.process(&mut colors);
+        assert!(limiter.is_limiting());
+        assert_eq!(colors[3], 0);
+        assert_eq!(colors[7], 0);
+        let milliamps = sent_milliamps(&limiter, &colors);
+        assert!(milliamps <= 30.0 && milliamps > 29.0, "{} mA", milliamps);
+    }
+}
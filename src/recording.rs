@@ -0,0 +1,150 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::config::PipelineConfig;
+
+/// File layout, all numbers are little endian:
+///
+/// ```text
+/// magic                 "WLEDREC" and format version (1 byte)
+/// led_horizontal_count  u32
+/// led_vertical_count    u32
+/// wled_type             u8, 2 is RGB, 3 is RGBW (same as `WledType`)
+/// bytes_per_led         u8, bytes of every LED in a frame
+/// frames                u32 milliseconds since the recording started,
+///                       then bytes_per_led bytes of every LED in `BorderColors` order
+/// ```
+const MAGIC: &[u8; 7] = b"WLEDREC";
+const VERSION: u8 = 1;
+const HEADER_LEN: u64 = 7 + 1 + 4 + 4 + 1 + 1;
+/// Frames are recorded as RGBW no matter `wled_type`, packets for RGB strips are encoded
+/// from them
+const BYTES_PER_LED: u8 = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordingHeader {
+    pub led_horizontal_count: u32,
+    pub led_vertical_count: u32,
+    pub wled_type: u8,
+    pub bytes_per_led: u8,
+}
+
+impl RecordingHeader {
+    pub fn frame_len(&self) -> usize {
+        ((self.led_horizontal_count + self.led_vertical_count) * 2) as usize
+            * self.bytes_per_led as usize
+    }
+}
+
+/// Writes every frame sent by one pipeline, so it can be replayed later
+pub struct Recorder {
+    file: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Creates `<dir>/<pipeline>-<unix time>.wlrec`
+    pub fn create(dir: &Path, config: &PipelineConfig) -> io::Result<(Self, PathBuf)> {
+        fs::create_dir_all(dir)?;
+        let name: String = config
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let path = dir.join(format!("{}-{}.wlrec", name, seconds));
+
+        let mut file = BufWriter::new(File::create(&path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&[VERSION])?;
+        file.write_all(&config.led_horizontal_count.to_le_bytes())?;
+        file.write_all(&config.led_vertical_count.to_le_bytes())?;
+        file.write_all(&[config.wled_type as u8, BYTES_PER_LED])?;
+
+        let recorder = Self {
+            file,
+            start: Instant::now(),
+        };
+        Ok((recorder, path))
+    }
+
+    /// `colors` holds 4 bytes (RGBW) per LED, processed but not faded in or dimmed by master
+    /// brightness yet
+    pub fn write(&mut self, colors: &[u8]) -> io::Result<()> {
+        let millis = self.start.elapsed().as_millis() as u32;
+        self.file.write_all(&millis.to_le_bytes())?;
+        self.file.write_all(colors)
+    }
+}
+
+/// Reads frames written by `Recorder`
+pub struct RecordingReader {
+    file: BufReader<File>,
+    pub header: RecordingHeader,
+}
+
+impl RecordingReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut magic = [0; 8];
+        file.read_exact(&mut magic)
+            .map_err(|_| invalid("not a recording"))?;
+        if &magic[..7] != MAGIC {
+            return Err(invalid("not a recording"));
+        }
+        if magic[7] != VERSION {
+            return Err(invalid("unsupported recording version"));
+        }
+
+        let mut header = [0; (HEADER_LEN - 8) as usize];
+        file.read_exact(&mut header)
+            .map_err(|_| invalid("recording header is truncated"))?;
+        let header = RecordingHeader {
+            led_horizontal_count: u32::from_le_bytes(header[0..4].try_into().unwrap()),
+            led_vertical_count: u32::from_le_bytes(header[4..8].try_into().unwrap()),
+            wled_type: header[8],
+            bytes_per_led: header[9],
+        };
+        if header.bytes_per_led != BYTES_PER_LED {
+            return Err(invalid(
+                "only recordings with 4 bytes per LED can be replayed",
+            ));
+        }
+
+        Ok(Self { file, header })
+    }
+
+    /// Next frame as milliseconds since the recording started and its colors, `None` at the
+    /// end. Frame cut short by a crash of the recording app counts as the end.
+    pub fn next_frame(&mut self) -> io::Result<Option<(u32, Vec<u8>)>> {
+        let mut millis = [0; 4];
+        let mut colors = vec![0; self.header.frame_len()];
+        for buffer in [&mut millis[..], &mut colors[..]] {
+            match self.file.read_exact(buffer) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(Some((u32::from_le_bytes(millis), colors)))
+    }
+
+    pub fn rewind(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(HEADER_LEN)).map(|_| ())
+    }
+}
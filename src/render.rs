@@ -0,0 +1,205 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use image::{imageops::FilterType, Rgb, RgbImage};
+use log::info;
+use serde::Serialize;
+
+use crate::{
+    config::PipelineConfig,
+    processing::ColorProcessor,
+    screen::{scale_factor, ColorExtractor, Dimension, PixelFormat},
+};
+
+const FRAME_EXTENSIONS: [&str; 4] = ["png", "jpg", "jpeg", "bmp"];
+
+#[derive(Serialize)]
+struct Timeline<'a> {
+    pipeline: &'a str,
+    led_horizontal_count: u32,
+    led_vertical_count: u32,
+    frames: Vec<TimelineFrame>,
+}
+
+#[derive(Serialize)]
+struct TimelineFrame {
+    file: String,
+    /// RGBW of every LED in `BorderColors` order
+    colors: Vec<[u8; 4]>,
+}
+
+/// Runs every image of `frames_dir` through color extraction and processing of `pipeline` the
+/// same way a captured frame would go, then writes the LED colors as a timeline (CSV or JSON
+/// by extension) and a PNG with a column per frame and a row per LED
+pub fn render(
+    frames_dir: &Path,
+    pipeline: &PipelineConfig,
+    timeline_path: &Path,
+    strip_path: &Path,
+) -> Result<(), String> {
+    let files = frame_files(frames_dir)?;
+    let mut processor = ColorProcessor::new(pipeline);
+    let mut extractor: Option<((u32, u32), ColorExtractor)> = None;
+    let mut frames = Vec::with_capacity(files.len());
+
+    for file in &files {
+        let image = image::open(file)
+            .map_err(|err| format!("Could not read {}: {}", file.display(), err))?;
+        // the GPU shrinks captured frames by mip levels before extraction
+        let scale = scale_factor(image.width(), image.height(), pipeline);
+        let size = (
+            (image.width() >> scale).max(1),
+            (image.height() >> scale).max(1),
+        );
+        let mut pixels = image
+            .resize_exact(size.0, size.1, FilterType::Triangle)
+            .into_rgba8()
+            .into_raw();
+
+        // frames of one folder usually share their size
+        if extractor.as_ref().map(|(s, _)| *s) != Some(size) {
+            let new_extractor = ColorExtractor::new(
+                Dimension {
+                    width: size.0,
+                    height: size.1,
+                },
+                Dimension {
+                    width: pipeline.led_horizontal_count,
+                    height: pipeline.led_vertical_count + 2,
+                },
+                pipeline.reducer,
+                pipeline.dominant_clusters,
+            )
+            .map_err(|err| err.to_string())?;
+            extractor = Some((size, new_extractor));
+        }
+        let (_, extractor) = extractor.as_mut().expect("extractor was just created");
+        let colors = extractor
+            .get_border_colors(&mut pixels, PixelFormat::Rgba8)
+            .map_err(|err| format!("{}: {}", file.display(), err))?;
+
+        let mut buffer = colors.concat();
+        processor.process(&mut buffer);
+        frames.push(buffer);
+    }
+
+    write_timeline(timeline_path, pipeline, &files, &frames)
+        .map_err(|err| format!("Could not write {}: {}", timeline_path.display(), err))?;
+    write_strip(strip_path, &frames)
+        .map_err(|err| format!("Could not write {}: {}", strip_path.display(), err))?;
+
+    info!(
+        pipeline = pipeline.name;
+        "Rendered {} frames into {} and {}",
+        frames.len(),
+        timeline_path.display(),
+        strip_path.display()
+    );
+    Ok(())
+}
+
+/// Images of `dir` in file name order
+fn frame_files(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries =
+        fs::read_dir(dir).map_err(|err| format!("Could not read {}: {}", dir.display(), err))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| {
+                    FRAME_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+                })
+        })
+        .collect();
+    files.sort();
+
+    if files.is_empty() {
+        return Err(format!(
+            "There are no {} frames in {}",
+            FRAME_EXTENSIONS.join(", "),
+            dir.display()
+        ));
+    }
+    Ok(files)
+}
+
+/// JSON holds every frame with its colors, CSV has a row for every LED of every frame
+fn write_timeline(
+    path: &Path,
+    pipeline: &PipelineConfig,
+    files: &[PathBuf],
+    frames: &[Vec<u8>],
+) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        let timeline = Timeline {
+            pipeline: &pipeline.name,
+            led_horizontal_count: pipeline.led_horizontal_count,
+            led_vertical_count: pipeline.led_vertical_count,
+            frames: files
+                .iter()
+                .zip(frames)
+                .map(|(file, colors)| TimelineFrame {
+                    file: file_name(file),
+                    colors: colors
+                        .chunks_exact(4)
+                        .map(|c| [c[0], c[1], c[2], c[3]])
+                        .collect(),
+                })
+                .collect(),
+        };
+        serde_json::to_writer_pretty(&mut out, &timeline)?;
+    } else {
+        writeln!(out, "frame,file,led,r,g,b,w")?;
+        for (index, (file, colors)) in files.iter().zip(frames).enumerate() {
+            let file = file_name(file).replace('"', "\"\"");
+            for (led, c) in colors.chunks_exact(4).enumerate() {
+                writeln!(
+                    out,
+                    "{},\"{}\",{},{},{},{},{}",
+                    index, file, led, c[0], c[1], c[2], c[3]
+                )?;
+            }
+        }
+    }
+
+    out.flush()
+}
+
+/// White channel is added to the others, the same as in the live preview
+fn write_strip(path: &Path, frames: &[Vec<u8>]) -> image::ImageResult<()> {
+    let led_count = frames.first().map_or(0, |colors| colors.len() / 4) as u32;
+    let mut strip = RgbImage::new(frames.len() as u32, led_count.max(1));
+
+    for (x, colors) in frames.iter().enumerate() {
+        for (y, c) in colors.chunks_exact(4).enumerate() {
+            let white = c[3];
+            strip.put_pixel(
+                x as u32,
+                y as u32,
+                Rgb([
+                    c[0].saturating_add(white),
+                    c[1].saturating_add(white),
+                    c[2].saturating_add(white),
+                ]),
+            );
+        }
+    }
+
+    strip.save(path)
+}
+
+fn file_name(file: &Path) -> String {
+    file.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
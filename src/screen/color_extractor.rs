@@ -1,7 +1,29 @@
 use std::num::NonZeroU32;
 
 use fast_image_resize as fir;
-use win_desktop_duplication::texture::ColorFormat;
+
+use crate::{
+    config::{ColorReducer, PipelineConfig},
+    error::{Error, Result},
+};
+
+use super::{
+    reducer::{border_zones, Zone, ZoneReducer},
+    BorderColors,
+};
+
+/// Reducers other than mean look at individual pixels, so they get a frame this many mip
+/// levels larger. That leaves roughly 8x8 pixels behind every LED.
+const REDUCER_DETAIL_LEVELS: u32 = 3;
+
+/// Byte order of 4 byte pixels handed to `ColorExtractor`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Desktop duplication
+    Bgra8,
+    /// Decoded images
+    Rgba8,
+}
 
 #[derive(PartialEq, PartialOrd)]
 pub struct Dimension {
@@ -9,36 +31,46 @@ pub struct Dimension {
     pub height: u32,
 }
 
-#[derive(Debug)]
-pub struct BorderColors {
-    pub top: Vec<u8>,
-    pub right: Vec<u8>,
-    pub bottom: Vec<u8>,
-    pub left: Vec<u8>,
-}
-
-impl BorderColors {
-    pub fn concat(&self) -> Vec<u8> {
-        let mut all_colors = Vec::new();
-        all_colors.reserve(self.top.len() + self.right.len() + self.bottom.len() + self.left.len());
-        all_colors.extend_from_slice(self.top.as_slice());
-        all_colors.extend_from_slice(self.right.as_slice());
-        all_colors.extend_from_slice(self.bottom.as_slice());
-        all_colors.extend_from_slice(self.left.as_slice());
-
-        all_colors
-    }
-}
-
 pub struct ColorExtractor<'a> {
     resizer: fir::Resizer,
     dest_image: fir::Image<'a>,
-    source_dim: Dimension,
+    source_width: NonZeroU32,
+    source_height: NonZeroU32,
     colors: BorderColors,
+    reducer: ColorReducer,
+    dominant_clusters: usize,
+    zone_reducer: ZoneReducer,
+    zones: Vec<(usize, usize, Zone)>,
 }
 
 impl<'a> ColorExtractor<'a> {
-    pub fn new(source_dim: Dimension, dest_dim: Dimension) -> Self {
+    /// `dest_dim` holds the LED layout, the top and bottom rows include the corners
+    pub fn new(
+        source_dim: Dimension,
+        dest_dim: Dimension,
+        reducer: ColorReducer,
+        dominant_clusters: u32,
+    ) -> Result<Self> {
+        let (Some(source_width), Some(source_height)) = (
+            NonZeroU32::new(source_dim.width),
+            NonZeroU32::new(source_dim.height),
+        ) else {
+            return Err(Error::Capture(format!(
+                "frame of {}x{} pixels is empty",
+                source_dim.width, source_dim.height
+            )));
+        };
+        let (Some(dest_width), Some(dest_height)) = (
+            NonZeroU32::new(dest_dim.width),
+            NonZeroU32::new(dest_dim.height).filter(|height| height.get() > 2),
+        ) else {
+            return Err(Error::Config(format!(
+                "layout of {} horizontal and {} vertical LEDs has an empty side",
+                dest_dim.width,
+                dest_dim.height.saturating_sub(2)
+            )));
+        };
+
         let mut colors = BorderColors {
             top: vec![],
             right: vec![],
@@ -50,46 +82,52 @@ impl<'a> ColorExtractor<'a> {
         colors.bottom.resize((dest_dim.width * 4) as usize, 0);
         colors.left.resize(((dest_dim.height - 2) * 4) as usize, 0);
 
-        Self {
+        let zones = match reducer {
+            ColorReducer::Mean => vec![],
+            _ => border_zones(
+                source_dim.width as usize,
+                source_dim.height as usize,
+                dest_dim.width as usize,
+                dest_dim.height as usize,
+            ),
+        };
+
+        Ok(Self {
             resizer: fir::Resizer::new(fir::ResizeAlg::Convolution(fir::FilterType::Bilinear)),
             dest_image: fast_image_resize::Image::new(
-                NonZeroU32::new(dest_dim.width).unwrap(),
-                NonZeroU32::new(dest_dim.height).unwrap(),
+                dest_width,
+                dest_height,
                 fast_image_resize::PixelType::U8x4,
             ),
-            source_dim,
+            source_width,
+            source_height,
             colors,
-        }
+            reducer,
+            dominant_clusters: dominant_clusters as usize,
+            zone_reducer: ZoneReducer::default(),
+            zones,
+        })
     }
 
-    pub fn get_border_colors(&mut self, pixels: &mut [u8], format: ColorFormat) -> &BorderColors {
-        let source_image = fast_image_resize::Image::from_slice_u8(
-            NonZeroU32::new(self.source_dim.width).unwrap(),
-            NonZeroU32::new(self.source_dim.height).unwrap(),
-            pixels,
-            fast_image_resize::PixelType::U8x4,
-        )
-        .unwrap();
-
-        self.resizer
-            .resize(&source_image.view(), &mut self.dest_image.view_mut())
-            .unwrap();
+    pub fn get_border_colors(
+        &mut self,
+        pixels: &mut [u8],
+        format: PixelFormat,
+    ) -> Result<&BorderColors> {
+        match self.reducer {
+            ColorReducer::Mean => self.resize(pixels)?,
+            reducer => self.reduce_zones(pixels, reducer)?,
+        }
 
         let width = self.dest_image.width().get() as usize;
         let height = self.dest_image.height().get() as usize;
 
         let buffer = self.dest_image.buffer_mut();
 
-        match format {
-            ColorFormat::ABGR8UNorm => {
-                buffer.iter_mut().skip(3).step_by(4).for_each(|c| *c = 0);
-                buffer.chunks_mut(4).for_each(|c| {
-                    let r = c[2];
-                    c[2] = c[0];
-                    c[0] = r;
-                });
-            }
-            _ => unimplemented!("sorry, this format is not implemented"),
+        // alpha would end up in the white channel
+        buffer.iter_mut().skip(3).step_by(4).for_each(|c| *c = 0);
+        if format == PixelFormat::Bgra8 {
+            buffer.chunks_mut(4).for_each(|c| c.swap(0, 2));
         }
 
         // top horizontal row
@@ -122,6 +160,120 @@ impl<'a> ColorExtractor<'a> {
                 .extend_from_slice(&buffer[buffer_begin..buffer_begin + 4]);
         }
 
-        &self.colors
+        Ok(&self.colors)
+    }
+
+    fn resize(&mut self, pixels: &mut [u8]) -> Result<()> {
+        let source_image = fast_image_resize::Image::from_slice_u8(
+            self.source_width,
+            self.source_height,
+            pixels,
+            fast_image_resize::PixelType::U8x4,
+        )
+        .map_err(|err| Error::Extraction(format!("invalid frame: {:?}", err)))?;
+
+        self.resizer
+            .resize(&source_image.view(), &mut self.dest_image.view_mut())
+            .map_err(|err| Error::Extraction(format!("could not resize frame: {:?}", err)))
+    }
+
+    /// Fills only border pixels of the destination image, inner pixels are never read
+    fn reduce_zones(&mut self, pixels: &[u8], reducer: ColorReducer) -> Result<()> {
+        let source_width = self.source_width.get() as usize;
+        let expected_len = source_width * self.source_height.get() as usize * 4;
+        if pixels.len() < expected_len {
+            return Err(Error::Extraction(format!(
+                "invalid frame: {} bytes instead of {}",
+                pixels.len(),
+                expected_len
+            )));
+        }
+        let dest_width = self.dest_image.width().get() as usize;
+        let buffer = self.dest_image.buffer_mut();
+
+        for &(col, row, zone) in &self.zones {
+            let color = match reducer {
+                ColorReducer::Median => self.zone_reducer.median(pixels, source_width, zone),
+                ColorReducer::Mode => self.zone_reducer.mode(pixels, source_width, zone),
+                _ => self
+                    .zone_reducer
+                    .dominant(pixels, source_width, zone, self.dominant_clusters),
+            };
+
+            let begin = (row * dest_width + col) * 4;
+            buffer[begin..begin + 4].copy_from_slice(&color);
+        }
+        Ok(())
+    }
+}
+
+/// Frames are shrunk by `2^scale_factor` before colors are extracted, as much as the LED layout
+/// allows, a frame with fewer pixels than LEDs is not shrunk at all
+pub fn scale_factor(width: u32, height: u32, config: &PipelineConfig) -> u32 {
+    let scale_factor_h = ((width as f32) / (config.led_horizontal_count as f32)).log2();
+    let scale_factor_v = ((height as f32) / (config.led_vertical_count as f32)).log2();
+
+    let scale_factor = scale_factor_h.min(scale_factor_v).max(0.0).floor() as u32;
+    if config.reducer != ColorReducer::Mean {
+        scale_factor.saturating_sub(REDUCER_DETAIL_LEVELS)
+    } else {
+        scale_factor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extractor(source: (u32, u32), layout: (u32, u32)) -> Result<ColorExtractor<'static>> {
+        ColorExtractor::new(
+            Dimension {
+                width: source.0,
+                height: source.1,
+            },
+            Dimension {
+                width: layout.0,
+                height: layout.1 + 2,
+            },
+            ColorReducer::Mean,
+            3,
+        )
+    }
+
+    #[test]
+    fn rejects_empty_frame_and_layout_with_empty_side() {
+        assert!(matches!(
+            extractor((0, 1080), (27, 14)),
+            Err(Error::Capture(_))
+        ));
+        assert!(matches!(
+            extractor((1920, 1080), (0, 14)),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            extractor((1920, 1080), (27, 0)),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn frame_smaller_than_layout_is_not_shrunk() {
+        let config = PipelineConfig::default();
+        assert_eq!(scale_factor(8, 4, &config), 0);
+        assert_eq!(scale_factor(1920, 1080, &config), 6);
+    }
+
+    #[test]
+    fn single_pixel_frame_lights_every_led() {
+        let mut extractor = extractor((1, 1), (3, 2)).unwrap();
+        let mut pixels = [10, 20, 30, 255];
+        let colors = extractor
+            .get_border_colors(&mut pixels, PixelFormat::Rgba8)
+            .unwrap()
+            .concat();
+        assert_eq!(colors.len(), (3 + 2) * 2 * 4);
+        for led in colors.chunks_exact(4) {
+            assert_eq!(led, [10, 20, 30, 0]);
+        }
     }
 }
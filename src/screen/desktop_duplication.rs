@@ -0,0 +1,355 @@
+use std::ptr::{copy, null};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+
+use win_desktop_duplication::devices::*;
+use win_desktop_duplication::errors::DDApiError;
+use win_desktop_duplication::outputs::{Display, DisplayMode};
+use win_desktop_duplication::texture::{ColorFormat, Texture};
+use win_desktop_duplication::{
+    co_init, set_process_dpi_awareness, DesktopDuplicationApi, DuplicationApiOptions,
+};
+use windows::Win32::Graphics::Direct3D11::{
+    ID3D11Device4, ID3D11DeviceContext4, ID3D11ShaderResourceView, ID3D11Texture2D,
+    D3D11_BIND_RENDER_TARGET, D3D11_BIND_SHADER_RESOURCE, D3D11_CPU_ACCESS_READ,
+    D3D11_MAPPED_SUBRESOURCE, D3D11_MAP_READ, D3D11_RESOURCE_MISC_GENERATE_MIPS,
+    D3D11_USAGE_DEFAULT, D3D11_USAGE_STAGING,
+};
+
+use crate::config::PipelineConfig;
+use crate::error::{Error, Result};
+use crate::metrics::PipelineMetrics;
+use crate::pacing::FramePacer;
+
+use super::{
+    color_extractor::{scale_factor, ColorExtractor, Dimension, PixelFormat},
+    BorderColors,
+};
+
+/// Single frames fail now and then (e.g. reading the cursor shape), only a display which keeps
+/// failing this long is reported so the capture is created again
+const ACQUIRE_ERROR_GRACE: Duration = Duration::from_secs(2);
+
+pub struct Screen {
+    config: PipelineConfig,
+    dupl: DesktopDuplicationApi,
+    display: Display,
+    display_mode: DisplayMode,
+    /// Paces capture when V-Sync is off
+    pacer: FramePacer,
+    scale_factor: u32,
+    frame_texture: Option<Texture>,
+    mip_texture: Option<ID3D11Texture2D>,
+    mip_srv: Option<ID3D11ShaderResourceView>,
+    device: ID3D11Device4,
+    ctx: ID3D11DeviceContext4,
+    color_extractor: Option<ColorExtractor<'static>>,
+    frame_data: Vec<u8>,
+    metrics: Arc<PipelineMetrics>,
+    /// First of the frames which could not be acquired in a row
+    failing_since: Option<Instant>,
+}
+
+impl From<DDApiError> for Error {
+    fn from(err: DDApiError) -> Self {
+        Error::Capture(format!("{:?}", err))
+    }
+}
+
+impl From<windows::core::Error> for Error {
+    fn from(err: windows::core::Error) -> Self {
+        Error::Capture(format!("{:?}", err))
+    }
+}
+
+impl Screen {
+    pub fn new(config: PipelineConfig, metrics: Arc<PipelineMetrics>) -> Result<Self> {
+        set_process_dpi_awareness();
+        co_init();
+
+        let adapter = AdapterFactory::new()
+            .get_adapter_by_idx(config.gpu_index)
+            .ok_or_else(|| Error::Config(format!("there is no GPU {}", config.gpu_index)))?;
+        // unplugged displays come back, so this is not a config error
+        let display = adapter
+            .get_display_by_idx(config.display_index)
+            .ok_or_else(|| {
+                Error::Capture(format!(
+                    "display {} of GPU {} is not connected",
+                    config.display_index, config.gpu_index
+                ))
+            })?;
+
+        // TODO solve HDR error
+        // TODO sometimes cursor error: Error Unexpected("failed to get DC for cursor image. Error { code: 0x887A0001, message: ...
+        let mut dupl = DesktopDuplicationApi::new(adapter, display.clone())?;
+        dupl.configure(DuplicationApiOptions {
+            skip_cursor: !config.include_cursor,
+        });
+
+        let (device, ctx) = dupl.get_device_and_ctx();
+        let pacer = FramePacer::new(config.max_fps);
+
+        let mut screen = Self {
+            config,
+            dupl,
+            display,
+            display_mode: Default::default(),
+            pacer,
+            scale_factor: 0,
+            frame_texture: None,
+            mip_texture: None,
+            mip_srv: None,
+            device,
+            ctx,
+            color_extractor: None,
+            frame_data: vec![],
+            metrics,
+            failing_since: None,
+        };
+
+        screen.refresh_display_mode()?;
+        Ok(screen)
+    }
+
+    /// Size of the mip level at `scale_factor`, like every mip level it is at least 1 pixel
+    fn scaled(&self, size: u32) -> u32 {
+        (size >> self.scale_factor).max(1)
+    }
+
+    fn refresh_display_mode(&mut self) -> Result<()> {
+        self.display_mode = self.display.get_current_display_mode()?;
+
+        self.scale_factor = scale_factor(
+            self.display_mode.width,
+            self.display_mode.height,
+            &self.config,
+        );
+
+        let frame_width = self.scaled(self.display_mode.width);
+        let frame_height = self.scaled(self.display_mode.height);
+
+        self.color_extractor = Some(ColorExtractor::new(
+            Dimension {
+                width: frame_width,
+                height: frame_height,
+            },
+            Dimension {
+                width: self.config.led_horizontal_count,
+                height: self.config.led_vertical_count + 2,
+            },
+            self.config.reducer,
+            self.config.dominant_clusters,
+        )?);
+        self.frame_data
+            .reserve((frame_width * frame_height * 4) as usize);
+
+        info!(
+            pipeline = self.config.name;
+            "Refreshing display mode: {:?}, scale factor: {}",
+            self.display_mode, self.scale_factor
+        );
+        Ok(())
+    }
+
+    pub fn wait_for_next_frame(&mut self) -> Result<()> {
+        if self.config.enable_v_sync {
+            self.display.wait_for_vsync()?;
+        } else {
+            self.pacer.wait();
+        }
+        Ok(())
+    }
+
+    /// `None` when there is no frame this time, errors mean the screen has to be created again
+    pub fn get_border_colors(&mut self) -> Result<Option<&BorderColors>> {
+        let capture_start = Instant::now();
+        match self.dupl.acquire_next_frame_now() {
+            Err(DDApiError::AccessLost) => {
+                self.metrics.access_lost.increment();
+                self.refresh_display_mode()?;
+                Ok(None)
+            }
+            Err(err) => {
+                let failing_since = *self.failing_since.get_or_insert_with(Instant::now);
+                if failing_since.elapsed() >= ACQUIRE_ERROR_GRACE {
+                    return Err(err.into());
+                }
+                warn!(pipeline = self.config.name; "Could not acquire a frame: {:?}", err);
+                Ok(None)
+            }
+            Ok(tex) => {
+                self.failing_since = None;
+                let format = pixel_format(self.get_resized_frame(&tex)?)?;
+                self.metrics.capture_time.observe(capture_start.elapsed());
+
+                let extraction_start = Instant::now();
+                let extractor = self.color_extractor.as_mut().unwrap();
+                let colors = extractor.get_border_colors(&mut self.frame_data, format)?;
+                self.metrics
+                    .extraction_time
+                    .observe(extraction_start.elapsed());
+
+                Ok(Some(colors))
+            }
+        }
+    }
+
+    fn get_resized_frame(&mut self, input_frame: &Texture) -> Result<ColorFormat> {
+        self.resize_into_frame_texture(input_frame)?;
+
+        let desc = input_frame.desc();
+        let width = self.scaled(desc.width);
+        let height = self.scaled(desc.height);
+
+        let raw_tex = self.frame_texture.as_mut().unwrap().as_raw_ref();
+        let sub_res: D3D11_MAPPED_SUBRESOURCE =
+            unsafe { self.ctx.Map(raw_tex, 0, D3D11_MAP_READ, 0) }
+                .map_err(|err| Error::Capture(format!("failed to map to cpu {:?}", err)))?;
+
+        match desc.format {
+            ColorFormat::ABGR8UNorm | ColorFormat::ARGB8UNorm | ColorFormat::AYUV => {
+                let total_size = width * height * 4;
+                self.frame_data.resize(total_size as usize, 0);
+                for i in 0..height {
+                    unsafe {
+                        copy(
+                            sub_res.pData.add((i * sub_res.RowPitch) as usize) as *const u8,
+                            self.frame_data.as_mut_ptr().add((i * width * 4) as _),
+                            (width * 4) as usize,
+                        );
+                    }
+                }
+            }
+            ColorFormat::YUV444 => {
+                let total_size = width * height * 3;
+                self.frame_data.resize(total_size as usize, 0);
+                for i in 0..(height * 3) {
+                    unsafe {
+                        copy(
+                            sub_res.pData.add((i * sub_res.RowPitch) as usize) as *const u8,
+                            self.frame_data.as_mut_ptr().add((i * width) as _),
+                            (width) as usize,
+                        );
+                    }
+                }
+            }
+            ColorFormat::NV12 => {
+                let total_size = width * height * 3 / 2;
+                self.frame_data.resize(total_size as usize, 0);
+                for i in 0..(3 * height / 2) {
+                    unsafe {
+                        copy(
+                            sub_res.pData.add((i * sub_res.RowPitch) as usize) as *const u8,
+                            self.frame_data.as_mut_ptr().add((i * width) as _),
+                            (width) as usize,
+                        );
+                    }
+                }
+            }
+
+            format => {
+                unsafe {
+                    self.ctx.Unmap(raw_tex, 0);
+                }
+                return Err(Error::Extraction(format!(
+                    "frames in {:?} format are not supported, is HDR turned on?",
+                    format
+                )));
+            }
+        }
+        unsafe {
+            self.ctx.Unmap(raw_tex, 0);
+        }
+
+        Ok(desc.format)
+    }
+
+    fn resize_into_frame_texture(&mut self, input_frame: &Texture) -> Result<()> {
+        self.ensure_frame_texture_created(input_frame)?;
+
+        unsafe {
+            self.ctx.CopySubresourceRegion(
+                self.mip_texture.as_ref().unwrap(),
+                0,
+                0,
+                0,
+                0,
+                input_frame.as_raw_ref(),
+                0,
+                null(),
+            );
+
+            self.ctx.GenerateMips(self.mip_srv.as_ref().unwrap());
+
+            self.ctx.CopySubresourceRegion(
+                self.frame_texture.as_mut().unwrap().as_raw_ref(),
+                0,
+                0,
+                0,
+                0,
+                self.mip_texture.as_ref().unwrap(),
+                self.scale_factor,
+                null(),
+            );
+            self.ctx.Flush();
+        }
+
+        Ok(())
+    }
+
+    fn ensure_frame_texture_created(&mut self, tex: &Texture) -> Result<()> {
+        if self.frame_texture.is_none()
+            || self.frame_texture.as_mut().unwrap().desc().height != self.scaled(tex.desc().height)
+            || self.frame_texture.as_mut().unwrap().desc().width != self.scaled(tex.desc().width)
+        {
+            self.frame_texture = None;
+            let mut desc = Default::default();
+            unsafe { tex.as_raw_ref().GetDesc(&mut desc) };
+            desc.Usage = D3D11_USAGE_STAGING;
+            desc.BindFlags = Default::default();
+            desc.CPUAccessFlags = D3D11_CPU_ACCESS_READ;
+            desc.MiscFlags = Default::default();
+            desc.Width = self.scaled(desc.Width);
+            desc.Height = self.scaled(desc.Height);
+
+            let new_tex = unsafe { self.device.CreateTexture2D(&desc, null()) }
+                .map_err(|err| Error::Capture(format!("failed to create texture. {:?}", err)))?;
+
+            let mut mip_desc = Default::default();
+            unsafe { tex.as_raw_ref().GetDesc(&mut mip_desc) }
+
+            mip_desc.BindFlags = D3D11_BIND_SHADER_RESOURCE | D3D11_BIND_RENDER_TARGET;
+            mip_desc.MiscFlags = D3D11_RESOURCE_MISC_GENERATE_MIPS;
+            mip_desc.MipLevels = 0;
+            mip_desc.Usage = D3D11_USAGE_DEFAULT;
+            mip_desc.CPUAccessFlags = Default::default();
+
+            unsafe {
+                debug!(pipeline = self.config.name; "Creating new mip texture");
+                self.mip_texture = Some(self.device.CreateTexture2D(&mip_desc, null())?);
+
+                self.mip_srv = Some(
+                    self.device
+                        .CreateShaderResourceView(self.mip_texture.as_ref().unwrap(), null())?,
+                );
+            }
+
+            self.frame_texture = Some(Texture::new(new_tex))
+        }
+
+        Ok(())
+    }
+}
+
+fn pixel_format(format: ColorFormat) -> Result<PixelFormat> {
+    match format {
+        ColorFormat::ABGR8UNorm => Ok(PixelFormat::Bgra8),
+        format => Err(Error::Extraction(format!(
+            "frames in {:?} format are not supported, is HDR turned on?",
+            format
+        ))),
+    }
+}
@@ -0,0 +1,320 @@
+// Kept free of other crate modules so `examples/reducer_bench.rs` can include it directly.
+
+/// Rectangle of source pixels which is reduced into color of one LED
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Zone {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Zones of all border cells of a `grid_width` x `grid_height` grid laid over the frame,
+/// returned together with column and row of the cell
+pub fn border_zones(
+    frame_width: usize,
+    frame_height: usize,
+    grid_width: usize,
+    grid_height: usize,
+) -> Vec<(usize, usize, Zone)> {
+    let zone = |col: usize, row: usize| {
+        let x = col * frame_width / grid_width;
+        let y = row * frame_height / grid_height;
+        Zone {
+            x,
+            y,
+            width: ((col + 1) * frame_width / grid_width).max(x + 1) - x,
+            height: ((row + 1) * frame_height / grid_height).max(y + 1) - y,
+        }
+    };
+
+    let mut zones = Vec::with_capacity(grid_width * 2 + grid_height * 2);
+    for row in 0..grid_height {
+        if row == 0 || row == grid_height - 1 {
+            zones.extend((0..grid_width).map(|col| (col, row, zone(col, row))));
+        } else {
+            zones.push((0, row, zone(0, row)));
+            zones.push((grid_width - 1, row, zone(grid_width - 1, row)));
+        }
+    }
+    zones
+}
+
+/// Reduces zones of a 4 bytes per pixel frame into a single color, channel order is kept.
+/// Holds scratch buffers so reducing does not allocate on every frame.
+#[derive(Default)]
+pub struct ZoneReducer {
+    keys: Vec<u16>,
+    samples: Vec<([f32; 3], f32)>,
+    centers: Vec<[f32; 3]>,
+    sums: Vec<[f32; 3]>,
+    weights: Vec<f32>,
+}
+
+impl ZoneReducer {
+    /// Median of every channel separately
+    pub fn median(&mut self, pixels: &[u8], frame_width: usize, zone: Zone) -> [u8; 4] {
+        let mut histograms = [[0u16; 256]; 4];
+        for_each_pixel(pixels, frame_width, zone, |p| {
+            histograms
+                .iter_mut()
+                .zip(p)
+                .for_each(|(h, &c)| h[c as usize] += 1);
+        });
+
+        let half = (zone.width * zone.height) as u32 / 2;
+        histograms.map(|histogram| {
+            let mut seen = 0u32;
+            histogram
+                .iter()
+                .position(|&count| {
+                    seen += count as u32;
+                    seen > half
+                })
+                .unwrap_or(0) as u8
+        })
+    }
+
+    /// Average of pixels falling into the most frequent bin of a 4 bits per channel histogram
+    pub fn mode(&mut self, pixels: &[u8], frame_width: usize, zone: Zone) -> [u8; 4] {
+        self.keys.clear();
+        for_each_pixel(pixels, frame_width, zone, |p| {
+            self.keys.push(quantize(p));
+        });
+        self.keys.sort_unstable();
+
+        let mut best_key = 0;
+        let mut best_count = 0;
+        let mut run_start = 0;
+        for i in 1..=self.keys.len() {
+            if i == self.keys.len() || self.keys[i] != self.keys[run_start] {
+                if i - run_start > best_count {
+                    best_count = i - run_start;
+                    best_key = self.keys[run_start];
+                }
+                run_start = i;
+            }
+        }
+
+        let mut sum = [0u32; 4];
+        let mut count = 0u32;
+        for_each_pixel(pixels, frame_width, zone, |p| {
+            if quantize(p) == best_key {
+                sum.iter_mut().zip(p).for_each(|(s, &c)| *s += c as u32);
+                count += 1;
+            }
+        });
+        sum.map(|s| (s / count.max(1)) as u8)
+    }
+
+    /// Weighted k-means, returns center of the heaviest cluster where saturated pixels weigh more.
+    /// `clusters` is clamped to 2-3, alpha channel of the result is 0.
+    pub fn dominant(
+        &mut self,
+        pixels: &[u8],
+        frame_width: usize,
+        zone: Zone,
+        clusters: usize,
+    ) -> [u8; 4] {
+        const ITERATIONS: usize = 4;
+        let clusters = clusters.clamp(2, 3);
+
+        self.samples.clear();
+        for_each_pixel(pixels, frame_width, zone, |p| {
+            let color = [p[0] as f32, p[1] as f32, p[2] as f32];
+            let max = color[0].max(color[1]).max(color[2]);
+            let min = color[0].min(color[1]).min(color[2]);
+            // gray pixels still count, saturated ones count up to 5 times more
+            let weight = 0.2 + (max - min) / 255.0;
+            self.samples.push((color, weight));
+        });
+
+        // deterministic seeding: heaviest sample first, then samples farthest from chosen centers
+        self.centers.clear();
+        let heaviest = self
+            .samples
+            .iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|s| s.0)
+            .unwrap_or_default();
+        self.centers.push(heaviest);
+        while self.centers.len() < clusters {
+            let farthest = self
+                .samples
+                .iter()
+                .map(|s| (s.0, nearest(&self.centers, s.0).1 * s.1))
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|s| s.0)
+                .unwrap_or_default();
+            self.centers.push(farthest);
+        }
+
+        self.weights.clear();
+        self.weights.resize(clusters, 0.0);
+        for _ in 0..ITERATIONS {
+            self.sums.clear();
+            self.sums.resize(clusters, [0.0; 3]);
+            self.weights.iter_mut().for_each(|w| *w = 0.0);
+
+            for (color, weight) in &self.samples {
+                let (index, _) = nearest(&self.centers, *color);
+                self.weights[index] += weight;
+                for (sum, c) in self.sums[index].iter_mut().zip(color) {
+                    *sum += c * weight;
+                }
+            }
+
+            for (i, center) in self.centers.iter_mut().enumerate() {
+                if self.weights[i] > 0.0 {
+                    *center = self.sums[i].map(|s| s / self.weights[i]);
+                }
+            }
+        }
+
+        let dominant = self
+            .weights
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(i, _)| self.centers[i])
+            .unwrap_or_default();
+
+        [
+            dominant[0].round() as u8,
+            dominant[1].round() as u8,
+            dominant[2].round() as u8,
+            0,
+        ]
+    }
+}
+
+fn for_each_pixel(pixels: &[u8], frame_width: usize, zone: Zone, mut f: impl FnMut(&[u8])) {
+    for row in zone.y..zone.y + zone.height {
+        let begin = (row * frame_width + zone.x) * 4;
+        pixels[begin..begin + zone.width * 4]
+            .chunks_exact(4)
+            .for_each(&mut f);
+    }
+}
+
+fn quantize(pixel: &[u8]) -> u16 {
+    ((pixel[0] as u16 >> 4) << 8) | ((pixel[1] as u16 >> 4) << 4) | (pixel[2] as u16 >> 4)
+}
+
+fn nearest(centers: &[[f32; 3]], color: [f32; 3]) -> (usize, f32) {
+    centers
+        .iter()
+        .map(|center| {
+            (0..3)
+                .map(|c| (center[c] - color[c]) * (center[c] - color[c]))
+                .sum::<f32>()
+        })
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or((0, 0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `width` x `height` frame where `paint` gives color of every pixel
+    fn frame(width: usize, height: usize, paint: impl Fn(usize, usize) -> [u8; 4]) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.extend_from_slice(&paint(x, y));
+            }
+        }
+        pixels
+    }
+
+    const RED: [u8; 4] = [250, 10, 10, 255];
+    const BLUE: [u8; 4] = [10, 10, 250, 255];
+    const GRAY: [u8; 4] = [128, 128, 128, 255];
+
+    fn whole(width: usize, height: usize) -> Zone {
+        Zone {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn border_zones_cover_border_cells_only() {
+        let zones = border_zones(40, 30, 4, 3);
+        // 4 top, 4 bottom, 1 left and 1 right cell in the middle row
+        assert_eq!(zones.len(), 10);
+        assert!(zones.contains(&(
+            0,
+            1,
+            Zone {
+                x: 0,
+                y: 10,
+                width: 10,
+                height: 10
+            }
+        )));
+        assert!(!zones.iter().any(|&(col, row, _)| (col, row) == (1, 1)));
+    }
+
+    #[test]
+    fn median_ignores_minority_outliers() {
+        // 3 of 4 columns are gray, one is bright white
+        let pixels = frame(4, 4, |x, _| if x == 3 { [255; 4] } else { GRAY });
+        let color = ZoneReducer::default().median(&pixels, 4, whole(4, 4));
+        assert_eq!(color, GRAY);
+    }
+
+    #[test]
+    fn mode_averages_most_frequent_bin() {
+        // 10 red-ish pixels slightly apart, 6 blue ones
+        let pixels = frame(4, 4, |x, y| match (x + y * 4) % 8 {
+            0..=2 => BLUE,
+            5 => [252, 12, 8, 255],
+            _ => RED,
+        });
+        let color = ZoneReducer::default().mode(&pixels, 4, whole(4, 4));
+        // (8 * 250 + 2 * 252) / 10, (8 * 10 + 2 * 12) / 10, (8 * 10 + 2 * 8) / 10
+        assert_eq!(&color[..3], &[250, 10, 9]);
+    }
+
+    #[test]
+    fn mode_only_looks_into_zone() {
+        let pixels = frame(4, 2, |x, _| if x < 2 { RED } else { BLUE });
+        let zone = Zone {
+            x: 2,
+            y: 0,
+            width: 2,
+            height: 2,
+        };
+        let color = ZoneReducer::default().mode(&pixels, 4, zone);
+        assert_eq!(color, BLUE);
+    }
+
+    #[test]
+    fn dominant_prefers_saturated_cluster() {
+        // half gray, half red, red weighs more because it is saturated
+        let pixels = frame(8, 2, |x, _| if x < 4 { GRAY } else { RED });
+        let color = ZoneReducer::default().dominant(&pixels, 8, whole(8, 2), 2);
+        assert_eq!(color, [250, 10, 10, 0]);
+    }
+
+    #[test]
+    fn dominant_picks_larger_of_saturated_clusters() {
+        let pixels = frame(8, 2, |x, _| if x < 5 { BLUE } else { RED });
+        let mut reducer = ZoneReducer::default();
+        assert_eq!(
+            reducer.dominant(&pixels, 8, whole(8, 2), 3),
+            [10, 10, 250, 0]
+        );
+        // buffers are reused by the next zone
+        let pixels = frame(8, 2, |x, _| if x < 2 { BLUE } else { RED });
+        assert_eq!(
+            reducer.dominant(&pixels, 8, whole(8, 2), 2),
+            [250, 10, 10, 0]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    path::Path,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use log::info;
+
+use crate::{
+    config::PipelineConfig,
+    error::{Error, Result},
+    recording::RecordingReader,
+};
+
+use super::BorderColors;
+
+/// Plays frames of a recording at their original timing instead of capturing the screen,
+/// starting over when the recording ends. Colors are already processed.
+pub struct Replay {
+    reader: RecordingReader,
+    /// When the current pass through the recording started
+    start: Instant,
+    next: (u32, Vec<u8>),
+    colors: BorderColors,
+}
+
+impl Replay {
+    pub fn open(path: &Path, config: &PipelineConfig) -> Result<Self> {
+        let open_error = |err| {
+            Error::Config(format!(
+                "could not open recording {}: {}",
+                path.display(),
+                err
+            ))
+        };
+        let mut reader = RecordingReader::open(path).map_err(open_error)?;
+
+        let header = &reader.header;
+        if header.led_horizontal_count != config.led_horizontal_count
+            || header.led_vertical_count != config.led_vertical_count
+        {
+            return Err(Error::Config(format!(
+                "recording {} has {}x{} LEDs, pipeline has {}x{}",
+                path.display(),
+                header.led_horizontal_count,
+                header.led_vertical_count,
+                config.led_horizontal_count,
+                config.led_vertical_count
+            )));
+        }
+        info!(
+            pipeline = config.name;
+            "Replaying {}, {}x{} LEDs recorded with wled_type {}",
+            path.display(),
+            header.led_horizontal_count,
+            header.led_vertical_count,
+            header.wled_type
+        );
+
+        let next = reader
+            .next_frame()
+            .map_err(open_error)?
+            .ok_or_else(|| Error::Config(format!("recording {} has no frames", path.display())))?;
+        let horizontal = (config.led_horizontal_count * 4) as usize;
+        let vertical = (config.led_vertical_count * 4) as usize;
+
+        Ok(Self {
+            reader,
+            start: Instant::now(),
+            next,
+            colors: BorderColors {
+                top: vec![0; horizontal],
+                right: vec![0; vertical],
+                bottom: vec![0; horizontal],
+                left: vec![0; vertical],
+            },
+        })
+    }
+
+    pub fn wait_for_next_frame(&self) {
+        let due = self.start + Duration::from_millis(self.next.0 as u64);
+        sleep(due.saturating_duration_since(Instant::now()));
+    }
+
+    pub fn get_border_colors(&mut self) -> Result<Option<&BorderColors>> {
+        let frame = match self.read_frame()? {
+            Some(frame) => frame,
+            None => {
+                self.reader.rewind().map_err(read_error)?;
+                self.start = Instant::now();
+                self.read_frame()?
+                    .ok_or_else(|| Error::Capture("recording became empty".to_string()))?
+            }
+        };
+        let (_, colors) = std::mem::replace(&mut self.next, frame);
+
+        let parts = [
+            &mut self.colors.top,
+            &mut self.colors.right,
+            &mut self.colors.bottom,
+            &mut self.colors.left,
+        ];
+        let mut offset = 0;
+        for part in parts {
+            let len = part.len();
+            part.copy_from_slice(&colors[offset..offset + len]);
+            offset += len;
+        }
+
+        Ok(Some(&self.colors))
+    }
+
+    fn read_frame(&mut self) -> Result<Option<(u32, Vec<u8>)>> {
+        self.reader.next_frame().map_err(read_error)
+    }
+}
+
+fn read_error(err: std::io::Error) -> Error {
+    Error::Capture(format!("could not read recording: {}", err))
+}
@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use log::warn;
+
+use crate::{config::PipelineConfig, error::Result, metrics::PipelineMetrics, pacing::FramePacer};
+
+use super::BorderColors;
+
+/// Desktop duplication is Windows only, elsewhere pipelines run without any frames so the
+/// rest of the app (config, signals, WLED connection) can still be exercised
+pub struct Screen {
+    pacer: FramePacer,
+}
+
+impl Screen {
+    pub fn new(config: PipelineConfig, _metrics: Arc<PipelineMetrics>) -> Result<Self> {
+        warn!(
+            pipeline = config.name;
+            "Screen capture is not supported on this platform, no colors will be sent"
+        );
+        Ok(Self {
+            pacer: FramePacer::new(config.max_fps),
+        })
+    }
+
+    pub fn wait_for_next_frame(&mut self) -> Result<()> {
+        self.pacer.wait();
+        Ok(())
+    }
+
+    pub fn get_border_colors(&mut self) -> Result<Option<&BorderColors>> {
+        Ok(None)
+    }
+}
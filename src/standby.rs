@@ -0,0 +1,200 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use crate::config::StandbyConfig;
+
+/// WLED answers on the local network right away, a slow answer is as good as none
+const HTTP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Standby entered or left by the latest frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StandbyChange {
+    Entered,
+    Left,
+}
+
+/// Watches extracted colors for a screen which stays black or shows the same picture
+pub struct StandbyDetector {
+    config: StandbyConfig,
+    /// Picture the screen has to differ from to count as changed
+    reference: Vec<u8>,
+    /// Since when the screen is black or unchanged
+    quiet_since: Option<Instant>,
+    active: bool,
+}
+
+impl StandbyDetector {
+    pub fn new(config: StandbyConfig) -> Self {
+        Self {
+            config,
+            reference: Vec::new(),
+            quiet_since: None,
+            active: false,
+        }
+    }
+
+    pub fn config(&self) -> &StandbyConfig {
+        &self.config
+    }
+
+    /// Nothing should be sent while this is set
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// `colors` are extracted colors before processing, processing could lift black
+    /// (`min_brightness`) or keep a dim picture moving
+    pub fn update(&mut self, colors: &[u8], now: Instant) -> Option<StandbyChange> {
+        let tolerance = self.config.tolerance;
+        let changed = colors.len() != self.reference.len()
+            || colors
+                .iter()
+                .zip(&self.reference)
+                .any(|(color, reference)| color.abs_diff(*reference) > tolerance);
+        if changed {
+            self.reference.clear();
+            self.reference.extend_from_slice(colors);
+        }
+        let black = colors
+            .iter()
+            .all(|&channel| channel <= self.config.black_threshold);
+
+        // a black screen is quiet even while the picture before it still fades out, any other
+        // picture is unchanged from the frame which showed it first
+        if changed && !black {
+            self.quiet_since = Some(now);
+        }
+        let quiet_since = *self.quiet_since.get_or_insert(now);
+        let active = now - quiet_since >= Duration::from_secs(self.config.after_seconds as u64);
+
+        let change = match (self.active, active) {
+            (false, true) => Some(StandbyChange::Entered),
+            (true, false) => Some(StandbyChange::Left),
+            _ => None,
+        };
+        self.active = active;
+        change
+    }
+}
+
+/// Switches WLED at `host` to `preset` through its JSON API
+pub fn apply_preset(host: &str, preset: u8) -> io::Result<()> {
+    let address = (host, 80)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no address"))?;
+    let mut stream = TcpStream::connect_timeout(&address, HTTP_TIMEOUT)?;
+    stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+    let body = format!("{{\"ps\":{}}}", preset);
+    write!(
+        stream,
+        "POST /json/state HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        host,
+        body.len(),
+        body
+    )?;
+
+    // WLED closes the connection after its answer, only the status of `HTTP/1.1 200 OK` matters
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+    let status = response.split(' ').nth(1).unwrap_or_default();
+    if status != "200" {
+        return Err(io::Error::other(format!(
+            "WLED answered with status {}",
+            status
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AFTER: Duration = Duration::from_secs(10);
+
+    fn detector() -> StandbyDetector {
+        StandbyDetector::new(StandbyConfig {
+            after_seconds: AFTER.as_secs() as u32,
+            black_threshold: 8,
+            tolerance: 2,
+            preset: None,
+        })
+    }
+
+    #[test]
+    fn black_screen_enters_standby_after_timeout() {
+        let mut detector = detector();
+        let start = Instant::now();
+        let last_picture = start + Duration::from_millis(500);
+        let mut black = vec![0; 16];
+
+        assert_eq!(detector.update(&[200; 16], start), None);
+        assert_eq!(detector.update(&[100; 16], last_picture), None);
+        // a black screen which still changes a little is black all the same
+        for second in 1..10 {
+            black[0] = (second % 2) * 8;
+            let now = start + Duration::from_secs(second as u64);
+            assert_eq!(detector.update(&black, now), None);
+        }
+        let timeout = last_picture + AFTER;
+        assert_eq!(
+            detector.update(&black, timeout - Duration::from_millis(1)),
+            None
+        );
+        assert!(!detector.is_active());
+        assert_eq!(
+            detector.update(&black, timeout),
+            Some(StandbyChange::Entered)
+        );
+        assert!(detector.is_active());
+        assert_eq!(detector.update(&black, timeout + AFTER), None);
+    }
+
+    #[test]
+    fn unchanged_screen_enters_standby_after_timeout() {
+        let mut detector = detector();
+        let start = Instant::now();
+        let mut picture = vec![120; 16];
+
+        assert_eq!(detector.update(&picture, start), None);
+        // changes within tolerance don't count
+        picture[3] = 122;
+        assert_eq!(detector.update(&picture, start + AFTER / 2), None);
+        assert_eq!(
+            detector.update(&picture, start + AFTER - Duration::from_millis(1)),
+            None
+        );
+        assert_eq!(
+            detector.update(&picture, start + AFTER),
+            Some(StandbyChange::Entered)
+        );
+    }
+
+    #[test]
+    fn change_restarts_timeout_and_leaves_standby() {
+        let mut detector = detector();
+        let start = Instant::now();
+
+        detector.update(&[120; 16], start);
+        detector.update(&[130; 16], start + AFTER / 2);
+        assert_eq!(detector.update(&[130; 16], start + AFTER), None);
+        assert_eq!(
+            detector.update(&[130; 16], start + AFTER / 2 + AFTER),
+            Some(StandbyChange::Entered)
+        );
+
+        assert_eq!(
+            detector.update(&[140; 16], start + AFTER * 2),
+            Some(StandbyChange::Left)
+        );
+        assert!(!detector.is_active());
+    }
+}